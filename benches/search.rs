@@ -0,0 +1,39 @@
+//! time a step of the exhaustive search over a wide grid, with many
+//! notes and a well stocked memory. compare against one thread with
+//! RAYON_NUM_THREADS=1 cargo bench.
+
+extern crate harmonymachine;
+extern crate rand;
+
+use std::time::Instant;
+
+use harmonymachine::judge::WeightedSum;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use harmonymachine::{remember, simplify, step_notes, Config, Frac, Memory};
+
+static STEPS: u32 = 20;
+
+fn main() {
+    let config = Config { ratio_limit: 23, ..Config::default() };
+    let notes: Vec<Frac> = (1..9).map(|b| Frac(1, b)).collect();
+    let mut memory = Memory::new();
+    for a in 1..=config.ratio_limit {
+        for b in 1..=config.ratio_limit {
+            remember(&[simplify(Frac(a, b))], &mut memory, &config);
+        }
+    }
+    let judge = WeightedSum::from_config(&config);
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    step_notes(&notes, &memory, &judge, &mut rng, &config);
+    let started = Instant::now();
+    for _ in 0..STEPS {
+        step_notes(&notes, &memory, &judge, &mut rng, &config);
+    }
+    let elapsed = started.elapsed() / STEPS;
+
+    println!("step_notes: {} notes, {} remembered: {:.2} ms per step",
+             notes.len(), memory.len(), elapsed.as_secs_f64() * 1000_f64);
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use {judge_harmony, judge_novelty, math, simplify, Config, Frac, Memory};
+
+/// familiarity-weighted centroid of the remembered ratios in log2 space,
+/// as a frequency ratio to the base note.
+pub fn tonal_center(memory: &Memory) -> f64 {
+    let mut weighted = 0_f64;
+    let mut total = 0_f64;
+    for (&Frac(a, b), &familiarity) in memory.iter().filter(|&(_, &familiarity)| familiarity > 0_f64) {
+        weighted += familiarity * math::log2((a as f64) / (b as f64));
+        total += familiarity;
+    }
+
+    if total > 0_f64 { math::pow(2_f64, weighted / total) } else { 1_f64 }
+}
+
+pub fn cents(ratio: f64) -> f64 {
+    1200_f64 * math::log2(ratio)
+}
+
+/// sensory roughness of two sine tones, after the plomp-levelt curve as
+/// parameterized by sethares.
+pub fn roughness(f1: f64, f2: f64) -> f64 {
+    let s = 0.24_f64 / (0.021_f64 * f1.min(f2) + 19_f64);
+    let d = (f1 - f2).abs();
+    math::exp(-3.5_f64 * s * d) - math::exp(-5.75_f64 * s * d)
+}
+
+/// summed roughness of every pair of frequencies.
+pub fn chord_roughness(freqs: &[f64]) -> f64 {
+    let mut rough = 0_f64;
+    for (i, &f1) in freqs.iter().enumerate() {
+        for &f2 in &freqs[i+1..] {
+            rough += roughness(f1, f2);
+        }
+    }
+
+    rough
+}
+
+/// interval content of a chord: the reduced ratio between every pair of
+/// notes, their mean tenney height log2(a*b), and the summed roughness
+/// of the sounding frequencies.
+pub fn interval_content(noteset: &[Frac], freqs: &[f64]) -> (Vec<Frac>, f64, f64) {
+    let mut intervals = vec![];
+    let mut heights = vec![];
+    for (i, x) in noteset.iter().enumerate() {
+        for y in &noteset[i+1..] {
+            heights.push(math::log2(x.height_over(y)));
+            if let Some(Frac(a, b)) = x.checked_div(y) {
+                intervals.push(if a >= b { Frac(a, b) } else { Frac(b, a) });
+            }
+        }
+    }
+    let tenney = heights.iter().sum::<f64>() / (heights.len().max(1) as f64);
+
+    (intervals, tenney, chord_roughness(freqs))
+}
+
+/// how tense a chord is, in [0, 1]: each note's mean tenney height to
+/// the others, over the most a pair of candidates with terms up to
+/// `limit` can have, weighted by how unfamiliar the note is next to the
+/// most familiar one remembered.
+pub fn tension(noteset: &[Frac], memory: &Memory, limit: u64) -> f64 {
+    if noteset.len() < 2 {
+        return 0_f64;
+    }
+
+    let most = math::log2((limit * limit) as f64);
+    let familiar = memory.values().cloned().fold(0_f64, f64::max);
+    let mut sum = 0_f64;
+    for (i, note) in noteset.iter().enumerate() {
+        let height = noteset.iter().enumerate()
+                            .filter(|&(j, _)| j != i)
+                            .map(|(_, other)| math::log2(note.height_over(other)))
+                            .sum::<f64>() / ((noteset.len() - 1) as f64);
+        let familiarity = *memory.get(note).unwrap_or(&0_f64);
+        let unfamiliarity = if familiar > 0_f64 { (1_f64 - familiarity / familiar).min(1_f64) } else { 1_f64 };
+        sum += (height / most).min(1_f64) * unfamiliarity;
+    }
+
+    sum / (noteset.len() as f64)
+}
+
+/// familiarity of every ratio in the search grid over a session, one
+/// column per chord change.
+pub struct Heatmap {
+    ratios: Vec<Frac>,
+    columns: Vec<Vec<f32>>,
+}
+
+impl Heatmap {
+    /// a heatmap of the ratios with terms up to `limit`.
+    pub fn new(limit: u64) -> Heatmap {
+        let mut ratios = vec![];
+        for a in 1..=limit {
+            for b in 1..=limit {
+                let ratio = simplify(Frac(a, b));
+                if !ratios.contains(&ratio) {
+                    ratios.push(ratio);
+                }
+            }
+        }
+        ratios.sort_by(|&Frac(a1, b1), &Frac(a2, b2)| (a1 as u128 * b2 as u128).cmp(&(a2 as u128 * b1 as u128)));
+
+        Heatmap { ratios, columns: vec![] }
+    }
+
+    pub fn record(&mut self, memory: &Memory) {
+        let column = self.ratios.iter()
+                                .map(|ratio| *memory.get(ratio).unwrap_or(&0_f64) as f32)
+                                .collect();
+        self.columns.push(column);
+    }
+
+    /// write a binary pgm with one row per ratio, highest at the top, and
+    /// brightness scaled to the most familiar entry of the session.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let max = self.columns.iter().flatten().cloned().fold(0_f32, f32::max);
+        let mut out = io::BufWriter::new(File::create(path)?);
+        write!(out, "P5\n{} {}\n255\n", self.columns.len(), self.ratios.len())?;
+        for row in (0..self.ratios.len()).rev() {
+            for column in &self.columns {
+                let level = if max > 0_f32 { column[row] / max } else { 0_f32 };
+                out.write_all(&[(level * 255_f32).round() as u8])?;
+            }
+        }
+
+        out.flush()
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Heatmap {
+        Heatmap::new(Config::default().ratio_limit)
+    }
+}
+
+/// running totals for the end-of-session summary.
+pub struct Stats {
+    started: Instant,
+    changes: u64,
+    rests: u64,
+    harmony_sum: f64,
+    novelty_sum: f64,
+    tension_sum: f64,
+    distinct: HashSet<Frac>,
+    familiarity: HashMap<Frac, f64>,
+    held: (u64, Vec<Frac>),
+    longest_held: (u64, Vec<Frac>),
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            started: Instant::now(),
+            changes: 0,
+            rests: 0,
+            harmony_sum: 0_f64,
+            novelty_sum: 0_f64,
+            tension_sum: 0_f64,
+            distinct: HashSet::new(),
+            familiarity: HashMap::new(),
+            held: (0, vec![]),
+            longest_held: (0, vec![]),
+        }
+    }
+
+    pub fn record(&mut self, noteset: &[Frac], memory: &Memory, resting: bool, config: &Config) {
+        self.changes += 1;
+        for (note, &familiarity) in memory.iter() {
+            *self.familiarity.entry(note.clone()).or_insert(0_f64) += familiarity;
+        }
+        if resting {
+            self.rests += 1;
+            self.held = (0, vec![]);
+            return;
+        }
+
+        self.harmony_sum += judge_harmony(noteset, memory, config.familiarity_ceiling());
+        self.novelty_sum += judge_novelty(noteset, memory, config.target_familiarity, config.familiarity_ceiling());
+        self.tension_sum += tension(noteset, memory, config.candidate_limit);
+        self.distinct.extend(noteset.iter().cloned());
+        if self.held.1 == noteset {
+            self.held.0 += 1;
+        } else {
+            self.held = (1, noteset.to_owned());
+        }
+        if self.held.0 > self.longest_held.0 {
+            self.longest_held = self.held.clone();
+        }
+    }
+
+    pub fn print(&self, steps: u64, samples: u64, pcm_hz: u64) {
+        let sounded = (self.changes - self.rests).max(1) as f64;
+        let spell = |notes: &[Frac]| {
+            notes.iter().map(|&Frac(a, b)| format!("{}/{}", a, b)).collect::<Vec<_>>().join(" ")
+        };
+        let mut familiar: Vec<(&Frac, &f64)> = self.familiarity.iter().collect();
+        familiar.sort_by(|x, y| y.1.partial_cmp(x.1).unwrap());
+        let top: Vec<String> = familiar.iter()
+                                       .take(10)
+                                       .map(|&(&Frac(a, b), f)| format!("{}/{} ({:.1})", a, b, f))
+                                       .collect();
+        let elapsed = self.started.elapsed();
+        let wall = elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64) / 1e9_f64;
+
+        eprintln!("steps: {} ({} chord changes, {} rests)", steps, self.changes, self.rests);
+        eprintln!("distinct ratios: {}", self.distinct.len());
+        eprintln!("average scores: harmony {:.3}, novelty {:.3}, tension {:.3}",
+                  self.harmony_sum / sounded, self.novelty_sum / sounded, self.tension_sum / sounded);
+        eprintln!("longest held chord: {} ({} changes)", spell(&self.longest_held.1), self.longest_held.0);
+        eprintln!("most familiar: {}", top.join(", "));
+        eprintln!("real-time factor: {:.1}x", (samples as f64) / (pcm_hz as f64) / wall);
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
@@ -0,0 +1,294 @@
+//! settings a HarmonyMachine is built with. every field can be left out
+//! of a config file and falls back to its default.
+
+use serde::de::{self, Deserialize, Deserializer};
+
+use synth::{Adsr, Doubling, EqBands, Gate, Interaction, Masking, OrnamentKind, Oscillator, Panning};
+use voice::VoiceConfig;
+use {Candidates, Frac, HarmonyMetric, Search};
+
+/// rates, pitch and memory settings for a HarmonyMachine.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// output sample rate in Hz.
+    pub pcm_hz: u64,
+    /// 1 for mono, 2 for stereo with the tones panned, or more for
+    /// outputs to route the machine and its voices to.
+    pub channels: u16,
+    /// output channels the machine's own notes play through, like a
+    /// voice's outputs.
+    pub outputs: Vec<usize>,
+    /// where tones sit across the stereo field when channels is 2, and
+    /// how far from the middle they may go, 1 being hard left or right.
+    pub panning: Panning,
+    pub pan_width: f64,
+    /// octaves from the base note at which Panning::ByPitch reaches
+    /// pan_width.
+    pub pan_octaves: f64,
+    /// with --play, mix the default input device into the output at
+    /// this gain, so a player monitoring through the same interface
+    /// hears themselves with the machine; none leaves it out.
+    pub passthrough: Option<f64>,
+    /// notesets per second.
+    pub steps_per_sec: u64,
+    /// harmonic rhythm: the notes change once every this many steps, and
+    /// the steps in between re-sound the same chord.
+    pub steps_per_change: u64,
+    /// frequency in Hz of the 1/1 ratio.
+    pub base_note: f64,
+    /// what each remembered familiarity is multiplied by every chord
+    /// change.
+    pub decay: f64,
+    /// familiarity added to a note each time it is played.
+    pub familiarity_increment: f64,
+    /// familiarity that remembering levels off toward, and that the judges
+    /// take familiarity as a share of; none lets it grow without bound.
+    pub max_familiarity: Option<f64>,
+    /// familiarities that decay closer to unfamiliar than this are
+    /// forgotten outright.
+    pub min_familiarity: f64,
+    /// keep memory from decaying through rests and pauses, so silence
+    /// doesn't cost the machine its vocabulary.
+    pub freeze_decay: bool,
+    /// how much faster or slower notes become familiar by how complex
+    /// they are; empty learns them all alike.
+    pub learning_rates: Vec<LearningRate>,
+    /// average familiarity judge_novelty steers the notes toward.
+    pub target_familiarity: f64,
+    /// how much harmony and novelty each count toward the default judge.
+    pub harmony_weight: f64,
+    pub novelty_weight: f64,
+    pub harmony_metric: HarmonyMetric,
+    /// weight of how familiar the move from the last notes is, against
+    /// 1 for the rest of the judge; 0 leaves it out.
+    pub transition_weight: f64,
+    /// judge score of a rest: a step falls silent when the best noteset
+    /// the search finds scores worse than this.
+    pub tacet_score: f64,
+    pub search: Search,
+    /// how the notes the search tries are proposed, every strategy
+    /// given adding its own.
+    pub candidates: Vec<Candidates>,
+    /// largest term of a proposed note, or ratio_limit if that's higher.
+    pub candidate_limit: u64,
+    /// how many of the most familiar ratios Candidates::MemoryProducts
+    /// multiplies together.
+    pub memory_product_top: usize,
+    /// how many ratios Candidates::Random draws each time.
+    pub random_candidates: usize,
+    /// search for the machine's next notes on another thread while the
+    /// current ones play, so a slow search doesn't hold up the audio. a
+    /// search that isn't done in time holds the chord a step longer.
+    /// only the machine's own search goes ahead: the voices' searches,
+    /// and the machine's when a modulation, morph, freeze or rollback
+    /// throws the one ahead away, still run on the render thread.
+    pub search_ahead: bool,
+    /// seeds the one random number generator behind the searches, the
+    /// voices and the synth's noise, so the same seed and config render
+    /// the same audio. search_ahead's timing can still change it.
+    pub seed: u64,
+    /// draw the decay, familiarity increment, target familiarity and
+    /// judge weights, and a few ratios to start out knowing, from the
+    /// seed, in place of the ones configured.
+    pub personality: bool,
+    /// largest numerator or denominator of the ratios searched over.
+    pub ratio_limit: u64,
+    /// largest prime allowed in the terms of the ratios searched over,
+    /// e.g. 5 for 5-limit; none leaves them free.
+    pub prime_limit: Option<u64>,
+    /// ratios like "9/8" the notes are kept to, in any octave; empty
+    /// leaves them free.
+    #[serde(deserialize_with = "ratios")]
+    pub scale: Vec<Frac>,
+    /// voices composing alongside the machine's own notes.
+    pub voices: Vec<VoiceConfig>,
+    /// roots the notes are composed over in turn, repeating; empty keeps
+    /// them over 1/1.
+    pub progression: Vec<Chord>,
+    /// lengths in steps, like "2" or "1/2", each noteset is picked to
+    /// last from; empty keeps every step one long.
+    #[serde(deserialize_with = "ratios")]
+    pub durations: Vec<Frac>,
+    /// a euclidean gate for each note of the machine's noteset in turn;
+    /// notes past the end of the list always play.
+    pub note_gates: Vec<Gate>,
+    /// extra copies of the machine's notes, each at a ratio to one of
+    /// them.
+    pub doublings: Vec<Doubling>,
+    /// tones closer than this many cents are pushed apart by octaves.
+    /// must stay under an octave.
+    pub min_spacing_cents: f64,
+    /// how many octaves up or down the voicing may move each note from
+    /// where its ratio puts it, to keep the chord smooth.
+    pub register_octaves: u32,
+    /// log the tonal center to stderr every this many chord changes; 0
+    /// stays quiet.
+    pub center_report_changes: u64,
+    /// log the interval content of every chord to stderr, tab separated:
+    /// the step, the intervals, their tenney height and roughness.
+    pub log_intervals: bool,
+    /// every this many phrases the base note moves to the most familiar
+    /// ratio; 0 never modulates.
+    pub modulation_phrases: u64,
+    /// respell memory relative to the new base note when modulating, so
+    /// familiar pitches stay familiar, rather than familiar ratios.
+    pub modulation_moves_memory: bool,
+    /// the envelope every tone is shaped by.
+    pub envelope: Adsr,
+    /// chance that a note swapped for one a simple interval away, of
+    /// tenney height log2(a*b) up to glide_max_tenney and no wider than
+    /// glide_max_cents as voiced, glides there over the step instead of
+    /// jumping.
+    pub glide_chance: f64,
+    pub glide_max_tenney: f64,
+    pub glide_max_cents: f64,
+    /// chance each step that a held tone of the machine's is decorated
+    /// with an ornament between it and its neighbours ornament_neighbor
+    /// above and below. voices set their own.
+    pub ornament_chance: f64,
+    pub ornament: OrnamentKind,
+    #[serde(deserialize_with = "ratio")]
+    pub ornament_neighbor: Frac,
+    /// ornament notes per second, slowed down if the ornament wouldn't
+    /// fit in a step.
+    pub ornament_rate_hz: f64,
+    /// level of a sub-oscillator an octave under the lowest tone,
+    /// relative to that tone; 0 turns it off.
+    pub sub_level: f64,
+    pub sub_oscillator: Oscillator,
+    /// how the oscillators of a step play off one another.
+    pub interaction: Interaction,
+    /// peak phase deviation in radians for Interaction::CrossModulation.
+    pub cross_mod_index: f64,
+    /// largest term of the ratio tones are paired by under
+    /// Interaction::CrossModulation.
+    pub pair_limit: u64,
+    /// level of the air layer, noise filtered around the tones of the
+    /// last chord; 0 turns it off.
+    pub air_level: f64,
+    /// quality factor of the air filters. higher hugs the tones closer.
+    pub air_q: f64,
+    /// turn down tones masked by louder ones; none leaves them be.
+    pub masking: Option<Masking>,
+    /// master eq over the whole mix.
+    pub eq: EqBands,
+    /// share of real time rendering may take. when a step takes longer
+    /// the harmonics per tone are cut back one at a time, and restored
+    /// while under half of it. 0 turns this off, which keeps output
+    /// reproducible.
+    pub cpu_budget: f64,
+}
+
+/// one root of a progression.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Chord {
+    /// ratio to base_note the notes are taken relative to.
+    #[serde(deserialize_with = "ratio")]
+    pub root: Frac,
+    /// chord changes it lasts.
+    pub changes: u64,
+}
+
+/// a learning rate for the notes up to a complexity.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LearningRate {
+    /// tenney height log2(a*b) of the most complex note it applies to.
+    pub max_tenney: f64,
+    /// what the familiarity increment is multiplied by.
+    pub rate: f64,
+}
+
+impl Config {
+    /// samples in one step.
+    pub fn step_samples(&self) -> u64 {
+        self.pcm_hz / self.steps_per_sec
+    }
+
+    /// what the judges take familiarity as a share of: max_familiarity,
+    /// or 1 for familiarity as it is.
+    pub fn familiarity_ceiling(&self) -> f64 {
+        self.max_familiarity.unwrap_or(1_f64)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            pcm_hz: 44100,
+            channels: 1,
+            outputs: vec![],
+            panning: Panning::ByPitch,
+            pan_width: 0.8_f64,
+            pan_octaves: 3_f64,
+            passthrough: None,
+            steps_per_sec: 4,
+            steps_per_change: 1,
+            base_note: 250_f64,
+            decay: 0.75_f64,
+            familiarity_increment: 0.1_f64,
+            max_familiarity: None,
+            min_familiarity: 0_f64,
+            freeze_decay: false,
+            learning_rates: vec![],
+            target_familiarity: 0.1_f64,
+            harmony_weight: 1_f64,
+            novelty_weight: 1_f64,
+            harmony_metric: HarmonyMetric::Product,
+            transition_weight: 0_f64,
+            tacet_score: 0.1_f64,
+            search: Search::Exhaustive,
+            candidates: vec![Candidates::Grid],
+            candidate_limit: 32,
+            memory_product_top: 8,
+            random_candidates: 32,
+            search_ahead: false,
+            seed: 0,
+            personality: false,
+            ratio_limit: 11,
+            prime_limit: None,
+            scale: vec![],
+            voices: vec![],
+            progression: vec![],
+            durations: vec![],
+            note_gates: vec![],
+            doublings: vec![],
+            min_spacing_cents: 0_f64,
+            register_octaves: 0,
+            center_report_changes: 0,
+            log_intervals: false,
+            modulation_phrases: 0,
+            modulation_moves_memory: true,
+            envelope: Adsr::default(),
+            glide_chance: 0_f64,
+            glide_max_tenney: 4.5_f64,
+            glide_max_cents: 1200_f64,
+            ornament_chance: 0_f64,
+            ornament: OrnamentKind::Trill,
+            ornament_neighbor: Frac(9, 8),
+            ornament_rate_hz: 16_f64,
+            sub_level: 0_f64,
+            sub_oscillator: Oscillator::Sine,
+            interaction: Interaction::Off,
+            cross_mod_index: 1_f64,
+            pair_limit: 11,
+            air_level: 0_f64,
+            air_q: 8_f64,
+            masking: None,
+            eq: EqBands::default(),
+            cpu_budget: 0_f64,
+        }
+    }
+}
+
+/// a ratio written as a string, see Frac::from_str.
+pub(crate) fn ratio<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Frac, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+}
+
+/// a list of ratios written as strings.
+fn ratios<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Frac>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?.iter().map(|ratio| ratio.parse().map_err(de::Error::custom)).collect()
+}
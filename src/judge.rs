@@ -0,0 +1,112 @@
+//! scoring functions for step_notes to minimize. scores are in [0, 1]
+//! and lower is better, like judge_harmony and judge_novelty.
+
+use transition::{judge_transition, Transitions};
+use {judge_harmony, judge_novelty, judge_tenney, Config, Frac, HarmonyMetric, Memory};
+
+/// something that scores a candidate noteset against the memory. judges
+/// are shared by reference with the search, so must be Sync too.
+pub trait Judge: Send + Sync {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64;
+}
+
+/// judge_harmony as a Judge, with familiarity as a share of `ceiling`.
+pub struct HarmonyJudge {
+    pub ceiling: f64,
+}
+
+impl Judge for HarmonyJudge {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64 {
+        judge_harmony(noteset, memory, self.ceiling)
+    }
+}
+
+/// judge_tenney as a Judge, with familiarity as a share of `ceiling`.
+pub struct TenneyJudge {
+    pub ceiling: f64,
+}
+
+impl Judge for TenneyJudge {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64 {
+        judge_tenney(noteset, memory, self.ceiling)
+    }
+}
+
+/// judge_novelty as a Judge, steering toward `target_familiarity`, with
+/// familiarity as a share of `ceiling`.
+pub struct NoveltyJudge {
+    pub target_familiarity: f64,
+    pub ceiling: f64,
+}
+
+impl Judge for NoveltyJudge {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64 {
+        judge_novelty(noteset, memory, self.target_familiarity, self.ceiling)
+    }
+}
+
+/// another judge with judge_transition from `previous` weighed in by
+/// `weight` against its 1, so progressions played before come back.
+pub struct TransitionJudge<'a> {
+    pub judge: &'a dyn Judge,
+    pub previous: &'a [Frac],
+    pub transitions: &'a Transitions,
+    pub weight: f64,
+    pub ceiling: f64,
+}
+
+impl<'a> Judge for TransitionJudge<'a> {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64 {
+        let score = self.judge.score(noteset, memory);
+        if self.weight <= 0_f64 {
+            return score;
+        }
+
+        (score + self.weight * judge_transition(self.previous, noteset, self.transitions, self.ceiling)) / (1_f64 + self.weight)
+    }
+}
+
+/// weighted average of other judges. weights needn't sum to 1.
+pub struct WeightedSum(pub Vec<(f64, Box<dyn Judge>)>);
+
+impl WeightedSum {
+    /// the default blend of harmony, by the metric in `config`, and
+    /// novelty, weighted per `config` and with familiarity as a share of
+    /// its max_familiarity.
+    pub fn from_config(config: &Config) -> WeightedSum {
+        WeightedSum(vec![
+            (config.harmony_weight, harmony_judge(config)),
+            (config.novelty_weight, Box::new(NoveltyJudge {
+                target_familiarity: config.target_familiarity,
+                ceiling: config.familiarity_ceiling(),
+            })),
+        ])
+    }
+}
+
+/// the harmony judge of `config`'s harmony_metric.
+pub fn harmony_judge(config: &Config) -> Box<dyn Judge> {
+    let ceiling = config.familiarity_ceiling();
+    match config.harmony_metric {
+        HarmonyMetric::Product => Box::new(HarmonyJudge { ceiling }),
+        HarmonyMetric::Tenney => Box::new(TenneyJudge { ceiling }),
+    }
+}
+
+impl Judge for WeightedSum {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64 {
+        let total: f64 = self.0.iter().map(|&(weight, _)| weight).sum();
+        if total <= 0_f64 {
+            return 0_f64;
+        }
+
+        self.0.iter().map(|(weight, judge)| weight * judge.score(noteset, memory)).sum::<f64>() / total
+    }
+}
+
+/// any function with judge's signature.
+impl<F> Judge for F where F: Fn(&[Frac], &Memory) -> f64 + Send + Sync {
+    fn score(&self, noteset: &[Frac], memory: &Memory) -> f64 {
+        self(noteset, memory)
+    }
+}
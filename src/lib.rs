@@ -0,0 +1,1854 @@
+//! generates music following two rules
+//!
+//! 1. notes played together should be simple integer ratios of one another
+//! 2. avoid repeating notes
+//!
+//! `HarmonyMachine` runs the whole thing and hands out samples; the
+//! pieces it is built from (`judge`, `step_notes`, `Memory`, ...) are
+//! exported for callers who want to drive the composition themselves.
+
+extern crate byteorder;
+extern crate serde;
+extern crate rand;
+extern crate rayon;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[cfg(feature = "playback")]
+extern crate cpal;
+#[cfg(feature = "deterministic")]
+extern crate libm;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::iter;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod math;
+mod motif;
+mod personality;
+mod rhythm;
+pub mod analysis;
+pub mod config;
+pub mod judge;
+pub mod midi;
+pub mod osc;
+#[cfg(feature = "playback")]
+pub mod playback;
+pub mod synth;
+pub mod transition;
+pub mod voice;
+pub mod wav;
+
+pub use config::{Chord, Config, LearningRate};
+pub use judge::Judge;
+
+use analysis::{cents, interval_content, tension, tonal_center, Heatmap, Stats};
+use judge::{harmony_judge, TransitionJudge, WeightedSum};
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use midi::MidiRecorder;
+use osc::OscSender;
+use motif::Motifs;
+use personality::personality;
+use rhythm::Rhythm;
+use transition::Transitions;
+use synth::{dynamics, fade_in, fade_out, limit, mark_onsets, release_waves, releases, route, routed_waves, sine_waves};
+use synth::{stereo_release_waves, stereo_waves};
+use synth::{sub_tone, tones, voicing};
+use synth::{harmonic_wave, Air, Equalizer, Glide, Ornament, Panning, Part, Release, Tone};
+use synth::{FADE_OUT_SECS, MAX_HARMONICS, PHRASE_STEPS};
+use voice::Voice;
+use wav::WavWriter;
+
+/// moves tried per step by Search::Annealing, and the temperature they
+/// cool over, in judge score.
+pub static ANNEAL_ITERATIONS: u64 = 200;
+pub static ANNEAL_START_TEMP: f64 = 0.02_f64;
+pub static ANNEAL_END_TEMP: f64 = 0.0005_f64;
+/// trajectories Search::Beam keeps at each depth, and how many steps
+/// ahead it looks.
+pub static BEAM_WIDTH: usize = 4;
+pub static BEAM_DEPTH: usize = 3;
+/// familiarity taken away from a ratio by an aversion, and what averted
+/// ratios' negative familiarity is multiplied by every chord change in
+/// place of the configured decay, so they stay avoided for a long time.
+pub static AVERSION: f64 = 1_f64;
+pub static AVERSION_DECAY: f64 = 0.98_f64;
+/// how many chord changes back rollback can go.
+pub static SNAPSHOTS: usize = 64;
+
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Clone, Debug, Serialize, Deserialize)]
+pub struct Frac(pub u64, pub u64);
+
+impl FromStr for Frac {
+    type Err = String;
+
+    /// "a/b", or "a" for a/1.
+    fn from_str(s: &str) -> Result<Frac, String> {
+        let (a, b) = s.split_once('/').unwrap_or((s, "1"));
+        let term = |t: &str| t.trim().parse::<u64>().ok().filter(|&n| n > 0);
+        match (term(a), term(b)) {
+            (Some(a), Some(b)) => Ok(simplify(Frac(a, b))),
+            _ => Err(format!("bad ratio {}, expected a/b of positive integers", s)),
+        }
+    }
+}
+
+impl Frac {
+    /// self * other in lowest terms, or None if a term is zero or doesn't
+    /// fit.
+    pub fn checked_mul(&self, &Frac(c, d): &Frac) -> Option<Frac> {
+        let &Frac(a, b) = self;
+        if a == 0 || b == 0 || c == 0 || d == 0 {
+            return None;
+        }
+        let (a, b) = lowest(a as u128 * c as u128, b as u128 * d as u128);
+        Some(Frac(u64::try_from(a).ok()?, u64::try_from(b).ok()?))
+    }
+
+    /// self / other in lowest terms, or None if a term is zero or doesn't
+    /// fit.
+    pub fn checked_div(&self, &Frac(c, d): &Frac) -> Option<Frac> {
+        self.checked_mul(&Frac(d, c))
+    }
+
+    /// a*b of self / other in lowest terms, worked out wide enough that
+    /// it can't overflow.
+    pub fn height_over(&self, &Frac(c, d): &Frac) -> f64 {
+        let &Frac(a, b) = self;
+        let (a, b) = lowest(a as u128 * d as u128, b as u128 * c as u128);
+        (a as f64) * (b as f64)
+    }
+}
+
+pub type Memory = BTreeMap<Frac, f64>;
+
+fn gcd(x: u64, y: u64) -> u64 {
+    if y == 0 {
+        x
+    } else {
+        gcd(y, x % y)
+    }
+}
+
+fn wide_gcd(x: u128, y: u128) -> u128 {
+    if y == 0 {
+        x
+    } else {
+        wide_gcd(y, x % y)
+    }
+}
+
+/// a/b in lowest terms, only reaching for u128 division when the terms
+/// need it.
+fn lowest(a: u128, b: u128) -> (u128, u128) {
+    let d = match (u64::try_from(a), u64::try_from(b)) {
+        (Ok(x), Ok(y)) => gcd(x, y) as u128,
+        _ => wide_gcd(a, b),
+    };
+    (a/d, b/d)
+}
+
+/// a/b in lowest terms, halved together until the terms fit in a Frac if
+/// they still don't, which keeps the pitch close but not exact.
+fn narrow(a: u128, b: u128) -> Frac {
+    let (mut a, mut b) = lowest(a, b);
+    while a > u64::MAX as u128 || b > u64::MAX as u128 {
+        a = (a / 2).max(1);
+        b = (b / 2).max(1);
+    }
+    simplify(Frac(a as u64, b as u64))
+}
+
+pub fn simplify(Frac(a, b): Frac) -> Frac {
+    let d = gcd(a, b);
+    Frac(a/d, b/d)
+}
+
+/// judge a set of notes based on harmony, with familiarity taken as a
+/// share of `ceiling`.
+/// range: floats in [0, 1] and lower is better.
+pub fn judge_harmony(noteset: &[Frac], memory: &Memory, ceiling: f64) -> f64 {
+    let mut harmony_sum = 0_f64;
+
+    for note in noteset {
+        for (remembered, &familiarity) in memory.iter() {
+            harmony_sum += familiarity.max(0_f64) * note.height_over(remembered);
+        }
+    }
+    let iterations = noteset.len()*memory.len();
+    if iterations == 0 {
+        return 0_f64;
+    }
+    let avg_harmony = harmony_sum/(iterations as f64)/ceiling;
+
+    (1_f64 - 1_f64/math::exp(avg_harmony/5_f64)).clamp(0_f64, 1_f64)
+}
+
+/// judge a set of notes based on harmony like judge_harmony, but by the
+/// tenney height log2(a*b) of each interval, which grows with the size
+/// of the terms rather than their product.
+/// range: floats in [0, 1] and lower is better.
+pub fn judge_tenney(noteset: &[Frac], memory: &Memory, ceiling: f64) -> f64 {
+    let mut height_sum = 0_f64;
+
+    for note in noteset {
+        for (remembered, &familiarity) in memory.iter() {
+            height_sum += familiarity.max(0_f64) * math::log2(note.height_over(remembered));
+        }
+    }
+    let iterations = noteset.len()*memory.len();
+    if iterations == 0 {
+        return 0_f64;
+    }
+    let avg_height = height_sum/(iterations as f64)/ceiling;
+
+    (1_f64 - 1_f64/math::exp(avg_height)).clamp(0_f64, 1_f64)
+}
+
+/// whether every prime factor of a note's terms is at most `limit`.
+pub fn within_prime_limit(&Frac(a, b): &Frac, limit: u64) -> bool {
+    let smooth = |mut n: u64| {
+        let mut p = 2;
+        while p <= limit && n > 1 {
+            while n.is_multiple_of(p) {
+                n /= p;
+            }
+            p += 1;
+        }
+        n == 1
+    };
+    smooth(a) && smooth(b)
+}
+
+/// judge a set of notes based on familiarity & novelty balance, with
+/// familiarity and the target taken as a share of `ceiling`.
+/// range: floats in [0, 1] and lower is better.
+pub fn judge_novelty(noteset: &[Frac], memory: &Memory, target_familiarity: f64, ceiling: f64) -> f64 {
+    let mut familiarity_sum = 0_f64;
+    for note in noteset {
+        let &familiarity = memory.get(note).unwrap_or(&0_f64);
+        familiarity_sum += familiarity;
+    }
+
+    let avg_familiarity = if noteset.is_empty() {
+        0_f64
+    } else {
+        familiarity_sum / (noteset.len() as f64)
+    };
+    let disparity = (target_familiarity - avg_familiarity).abs() / ceiling;
+
+    (1_f64 - 1_f64/math::exp(disparity)).clamp(0_f64, 1_f64)
+}
+
+/// judge a set of notes, blending harmony and novelty by the weights in
+/// `config`. the default judge of a HarmonyMachine.
+/// range: floats in [0, 1] and lower is better.
+pub fn judge(noteset: &[Frac], memory: &Memory, config: &Config) -> f64 {
+    WeightedSum::from_config(config).score(noteset, memory)
+}
+
+/// decay every familiarity, dropping the ones that come within the
+/// config's min_familiarity of unfamiliar.
+pub fn forget(memory: &mut Memory, config: &Config) {
+    for val in memory.values_mut() {
+        *val *= if *val < 0_f64 { AVERSION_DECAY } else { config.decay };
+    }
+    if config.min_familiarity > 0_f64 {
+        memory.retain(|_, val| val.abs() >= config.min_familiarity);
+    }
+}
+
+/// push a ratio below unfamiliar by `amount`, so judge_novelty steers
+/// away from it until it decays back, whatever familiarity it had.
+pub fn avert(note: &Frac, memory: &mut Memory, amount: f64) {
+    let val = memory.entry(note.clone()).or_insert(0_f64);
+    *val = val.min(0_f64) - amount;
+}
+
+/// what the familiarity increment is multiplied by for `note`: the rate
+/// of the first of `rates` whose max_tenney its tenney height log2(a*b)
+/// is within, or 1 if there's none.
+pub fn learning_rate(note: &Frac, rates: &[LearningRate]) -> f64 {
+    let tenney = math::log2(note.height_over(&Frac(1, 1)));
+    rates.iter().find(|rate| tenney <= rate.max_tenney).map_or(1_f64, |rate| rate.rate)
+}
+
+/// add the config's familiarity_increment to each note, scaled by its
+/// learning_rate. with a max_familiarity the increment shrinks as a note
+/// nears it, so familiarity levels off there instead of growing without
+/// bound.
+pub fn remember(note_set: &[Frac], memory: &mut Memory, config: &Config) {
+    for note in note_set {
+        let increase = config.familiarity_increment * learning_rate(note, &config.learning_rates);
+        let val = memory.entry(note.clone()).or_insert(0_f64);
+        *val += match config.max_familiarity {
+            Some(max) => increase * (1_f64 - *val / max).max(0_f64),
+            None => increase,
+        };
+    }
+}
+
+/// a memory that knows `notes` in proportion to how often each occurs,
+/// the most common as familiar as a note played every chord change
+/// settles at by `config`.
+pub fn learn(notes: &[Frac], config: &Config) -> Memory {
+    let mut counts = Memory::new();
+    for note in notes {
+        *counts.entry(note.clone()).or_insert(0_f64) += 1_f64;
+    }
+    let most = counts.values().cloned().fold(0_f64, f64::max);
+    let settled = if config.decay < 1_f64 {
+        config.familiarity_increment / (1_f64 - config.decay)
+    } else {
+        config.familiarity_increment
+    };
+    let top = config.max_familiarity.map_or(settled, |max| settled.min(max));
+    for count in counts.values_mut() {
+        *count *= top / most;
+    }
+
+    counts
+}
+
+/// a weighted average of memories over every ratio any of them holds,
+/// a ratio missing from one counting as unfamiliar there.
+pub fn blend(memories: &[(f64, &Memory)]) -> Memory {
+    let total: f64 = memories.iter().map(|&(weight, _)| weight).sum();
+    let mut blended = Memory::new();
+    if total <= 0_f64 {
+        return blended;
+    }
+
+    for &(weight, memory) in memories {
+        for (note, &familiarity) in memory.iter() {
+            *blended.entry(note.clone()).or_insert(0_f64) += weight * familiarity / total;
+        }
+    }
+
+    blended
+}
+
+/// `from` moved a fraction `t` of the way to `to`, ratio by ratio.
+pub fn interpolate(from: &Memory, to: &Memory, t: f64) -> Memory {
+    let t = t.clamp(0_f64, 1_f64);
+    blend(&[(1_f64 - t, from), (t, to)])
+}
+
+/// ways of proposing replacement notes to step_notes, any of which can
+/// be chosen in the config's candidates.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Candidates {
+    /// every a/b with a and b up to the config's ratio_limit.
+    Grid,
+    /// the current notes moved one step along the 2, 3, 5 or 7 axis of
+    /// the prime lattice.
+    Lattice,
+    /// products of two of the config's memory_product_top most familiar
+    /// ratios.
+    MemoryProducts,
+    /// the config's random_candidates ratios drawn with each term n
+    /// weighted by 1/n, so simple ratios come up far more often than
+    /// complex ones.
+    Random,
+}
+
+impl FromStr for Candidates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Candidates, String> {
+        match s {
+            "grid" => Ok(Candidates::Grid),
+            "lattice" => Ok(Candidates::Lattice),
+            "memory_products" => Ok(Candidates::MemoryProducts),
+            "random" => Ok(Candidates::Random),
+            _ => Err(format!("unknown candidates {}, expected grid, lattice, memory_products or random", s)),
+        }
+    }
+}
+
+/// replacement notes proposed by every strategy in the config's
+/// candidates, folded into the search range and kept to terms of at most
+/// its candidate_limit, or ratio_limit if that's higher, and to its
+/// prime_limit. with a scale in `config` only its degrees are kept, and
+/// all of them are proposed.
+pub fn candidates<R: Rng>(note_set: &[Frac], memory: &Memory, rng: &mut R, config: &Config) -> Vec<Frac> {
+    let limit = config.candidate_limit.max(config.ratio_limit);
+    let mut proposed = scale_notes(&config.scale, config.candidate_limit);
+    for strategy in &config.candidates {
+        match *strategy {
+            Candidates::Grid => {
+                for a in 1..=config.ratio_limit {
+                    for b in 1..=config.ratio_limit {
+                        proposed.push(Frac(a, b));
+                    }
+                }
+            }
+            Candidates::Lattice => {
+                for note in note_set {
+                    for &p in &[2, 3, 5, 7] {
+                        proposed.extend(note.checked_mul(&Frac(p, 1)));
+                        proposed.extend(note.checked_div(&Frac(p, 1)));
+                    }
+                }
+            }
+            Candidates::MemoryProducts => {
+                let mut familiar: Vec<(&Frac, &f64)> = memory.iter().collect();
+                familiar.sort_by(|x, y| y.1.partial_cmp(x.1).unwrap());
+                familiar.truncate(config.memory_product_top);
+                for &(x, _) in &familiar {
+                    for &(y, _) in &familiar {
+                        proposed.extend(x.checked_mul(y));
+                    }
+                }
+            }
+            Candidates::Random => {
+                let terms = match WeightedIndex::new((1..=limit).map(|n| 1_f64 / n as f64)) {
+                    Ok(terms) => terms,
+                    Err(_) => continue,
+                };
+                for _ in 0..config.random_candidates {
+                    proposed.push(Frac(rng.sample(&terms) as u64 + 1, rng.sample(&terms) as u64 + 1));
+                }
+            }
+        }
+    }
+
+    let mut found = vec![];
+    for possibility in proposed.into_iter().map(fold) {
+        let Frac(a, b) = possibility;
+        if a <= limit && b <= limit && in_scale(&possibility, &config.scale)
+            && config.prime_limit.is_none_or(|primes| within_prime_limit(&possibility, primes))
+            && !note_set.contains(&possibility) && !found.contains(&possibility) {
+            found.push(possibility);
+        }
+    }
+
+    found
+}
+
+/// whether a note is some octave of a degree of `scale`. every note is
+/// in an empty scale.
+pub fn in_scale(note: &Frac, scale: &[Frac]) -> bool {
+    scale.is_empty() || scale.iter().any(|degree| octave_reduce(degree.clone()) == octave_reduce(note.clone()))
+}
+
+/// every octave of the degrees of `scale` in the search range, with
+/// terms up to `limit`.
+fn scale_notes(scale: &[Frac], limit: u64) -> Vec<Frac> {
+    let mut found = vec![];
+    for degree in scale {
+        for octave in 0..5 {
+            let up = degree.checked_mul(&Frac(1 << octave, 1));
+            let down = degree.checked_div(&Frac(1 << octave, 1));
+            for note in up.into_iter().chain(down).map(fold) {
+                let Frac(c, d) = note;
+                if c <= limit && d <= limit && !found.contains(&note) {
+                    found.push(note);
+                }
+            }
+        }
+    }
+
+    found
+}
+
+/// move notes outside `scale` to the nearest degree in pitch, with terms
+/// up to `limit`, that isn't already among them.
+pub fn snap_to_scale(notes: &mut [Frac], scale: &[Frac], limit: u64) {
+    let degrees = scale_notes(scale, limit);
+    let pitch = |&Frac(a, b): &Frac| math::log2((a as f64) / (b as f64));
+    for i in 0..notes.len() {
+        if in_scale(&notes[i], scale) {
+            continue;
+        }
+        let target = pitch(&notes[i]);
+        let nearest = degrees.iter()
+                             .filter(|degree| !notes.contains(degree))
+                             .min_by(|x, y| (pitch(x) - target).abs().partial_cmp(&(pitch(y) - target).abs()).unwrap());
+        if let Some(degree) = nearest {
+            notes[i] = degree.clone();
+        }
+    }
+}
+
+/// how the next noteset is searched for.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Search {
+    /// step_notes: the best single-note swap.
+    Exhaustive,
+    /// anneal_notes: a random walk of swaps that settles as it cools.
+    Annealing,
+    /// beam_notes: the first move of the best few-step trajectory.
+    Beam,
+}
+
+impl FromStr for Search {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Search, String> {
+        match s {
+            "exhaustive" => Ok(Search::Exhaustive),
+            "annealing" => Ok(Search::Annealing),
+            "beam" => Ok(Search::Beam),
+            _ => Err(format!("unknown search {}, expected exhaustive, annealing or beam", s)),
+        }
+    }
+}
+
+/// how the default judge scores harmony.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HarmonyMetric {
+    /// judge_harmony: the product a*b of each interval.
+    Product,
+    /// judge_tenney: its tenney height log2(a*b).
+    Tenney,
+}
+
+impl FromStr for HarmonyMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HarmonyMetric, String> {
+        match s {
+            "product" => Ok(HarmonyMetric::Product),
+            "tenney" => Ok(HarmonyMetric::Tenney),
+            _ => Err(format!("unknown harmony metric {}, expected product or tenney", s)),
+        }
+    }
+}
+
+/// step to a set of notes that minimizes the judge function. `rng` draws
+/// Candidates::Random.
+pub fn step_notes<R: Rng>(note_set: &[Frac], memory: &Memory, judge: &dyn Judge, rng: &mut R,
+                          config: &Config) -> Vec<Frac> {
+    let mut best: Vec<Frac> = note_set.to_owned();
+    let mut best_score = 1_f64;
+    for (note_set2, score) in swaps(note_set, memory, judge, rng, config) {
+        if score < best_score {
+            best = note_set2;
+            best_score = score;
+        }
+    }
+
+    best
+}
+
+/// simulated annealing over single-note swaps: each move swaps a random
+/// note for a random candidate, and is kept if it judges better or, with
+/// a chance that shrinks as the temperature cools, worse. returns the
+/// best noteset visited other than the one it started from.
+pub fn anneal_notes<R: Rng>(note_set: &[Frac], memory: &Memory, judge: &dyn Judge, rng: &mut R,
+                            config: &Config) -> Vec<Frac> {
+    let possibilities = candidates(note_set, memory, rng, config);
+    if note_set.is_empty() || possibilities.is_empty() {
+        return note_set.to_owned();
+    }
+
+    let mut current = note_set.to_owned();
+    let mut current_score = judge.score(&current, memory);
+    let mut best: Option<(Vec<Frac>, f64)> = None;
+    for i in 0..ANNEAL_ITERATIONS {
+        let cooled = (i as f64) / (ANNEAL_ITERATIONS as f64);
+        let temperature = ANNEAL_START_TEMP * math::pow(ANNEAL_END_TEMP / ANNEAL_START_TEMP, cooled);
+        let possibility = &possibilities[rng.gen_range(0..possibilities.len())];
+        if current.contains(possibility) {
+            continue;
+        }
+        let mut moved = current.clone();
+        moved.remove(rng.gen_range(0..moved.len()));
+        moved.push(possibility.clone());
+        let score = judge.score(&moved, memory);
+
+        if best.as_ref().is_none_or(|&(_, best_score)| score < best_score) {
+            best = Some((moved.clone(), score));
+        }
+        if score < current_score || rng.gen::<f64>() < math::exp((current_score - score) / temperature) {
+            current = moved;
+            current_score = score;
+        }
+    }
+
+    best.map_or_else(|| note_set.to_owned(), |(notes, _)| notes)
+}
+
+/// the next notes by the search chosen in `config`.
+pub fn search_notes(note_set: &[Frac], memory: &Memory, judge: &dyn Judge, rng: &mut StdRng, config: &Config) -> Vec<Frac> {
+    match config.search {
+        Search::Exhaustive => step_notes(note_set, memory, judge, rng, config),
+        Search::Annealing => anneal_notes(note_set, memory, judge, rng, config),
+        Search::Beam => beam_notes(note_set, memory, judge, rng, config),
+    }
+}
+
+/// every single-note swap of a noteset, with its score, in order. the
+/// scoring is spread over rayon's threads.
+fn swaps<R: Rng>(note_set: &[Frac], memory: &Memory, judge: &dyn Judge, rng: &mut R,
+                 config: &Config) -> Vec<(Vec<Frac>, f64)> {
+    let possibilities = candidates(note_set, memory, rng, config);
+    let mut found = vec![];
+    for i in 0..note_set.len() {
+        for possibility in &possibilities {
+            let mut swapped = note_set.to_owned();
+            swapped.remove(i);
+            swapped.push(possibility.clone());
+            found.push(swapped);
+        }
+    }
+
+    found.into_par_iter()
+         .map(|swapped| {
+             let score = judge.score(&swapped, memory);
+             (swapped, score)
+         })
+         .collect()
+}
+
+/// beam search over BEAM_DEPTH steps of single-note swaps, forgetting
+/// and remembering along each trajectory the way the machine would, and
+/// keeping the BEAM_WIDTH with the lowest summed score at each depth.
+/// returns the first noteset of the best trajectory.
+pub fn beam_notes<R: Rng>(note_set: &[Frac], memory: &Memory, judge: &dyn Judge, rng: &mut R,
+                          config: &Config) -> Vec<Frac> {
+    // (first move, current notes, memory after it, summed score)
+    let mut beam: Vec<(Vec<Frac>, Vec<Frac>, Memory, f64)> = vec![];
+    for (notes, score) in swaps(note_set, memory, judge, rng, config) {
+        beam.push((notes.clone(), notes, memory.clone(), score));
+    }
+
+    for depth in 0..BEAM_DEPTH {
+        beam.sort_by(|x, y| x.3.partial_cmp(&y.3).unwrap());
+        beam.truncate(BEAM_WIDTH);
+        if depth + 1 == BEAM_DEPTH {
+            break;
+        }
+
+        let mut next = vec![];
+        for (first, notes, mut memory, total) in beam {
+            remember(&notes, &mut memory, config);
+            forget(&mut memory, config);
+            for (moved, score) in swaps(&notes, &memory, judge, rng, config) {
+                next.push((first.clone(), moved, memory.clone(), total + score));
+            }
+        }
+        beam = next;
+    }
+
+    beam.into_iter().next().map_or_else(|| note_set.to_owned(), |(first, _, _, _)| first)
+}
+
+/// the ratio with terms up to `limit` closest to `ratio` in pitch.
+pub fn nearest_ratio(ratio: f64, limit: u64) -> Frac {
+    let mut best = Frac(1, 1);
+    let mut best_distance = f64::INFINITY;
+    for a in 1..=limit {
+        for b in 1..=limit {
+            let distance = math::log2((a as f64) / (b as f64) / ratio).abs();
+            if distance < best_distance {
+                best = simplify(Frac(a, b));
+                best_distance = distance;
+            }
+        }
+    }
+
+    best
+}
+
+/// fold a ratio by octaves into [1/12, 12], the range the search uses.
+pub fn fold(Frac(a, b): Frac) -> Frac {
+    let (mut a, mut b) = (a as u128, b as u128);
+    while a > 12 * b {
+        b *= 2;
+    }
+    while 12 * a < b {
+        a *= 2;
+    }
+    narrow(a, b)
+}
+
+/// fold a ratio by octaves into [1, 2).
+pub fn octave_reduce(Frac(a, b): Frac) -> Frac {
+    let (mut a, mut b) = (a as u128, b as u128);
+    while a >= 2 * b {
+        b *= 2;
+    }
+    while a < b {
+        a *= 2;
+    }
+    narrow(a, b)
+}
+
+/// `note` spelled relative to `by`, folded into the search grid of terms
+/// up to `limit`, or None if it falls off the grid.
+pub fn respell(note: &Frac, by: &Frac, limit: u64) -> Option<Frac> {
+    let Frac(e, f) = fold(note.checked_div(by)?);
+    if e <= limit && f <= limit { Some(Frac(e, f)) } else { None }
+}
+
+/// the root of `progression` at chord change number `change`, 1/1 if
+/// there is none.
+pub fn progression_root(progression: &[Chord], change: u64) -> f64 {
+    let length: u64 = progression.iter().map(|chord| chord.changes).sum();
+    if length == 0 {
+        return 1_f64;
+    }
+
+    let mut position = change % length;
+    for chord in progression {
+        if position < chord.changes {
+            let Frac(a, b) = chord.root;
+            return (a as f64) / (b as f64);
+        }
+        position -= chord.changes;
+    }
+
+    1_f64
+}
+
+/// the most familiar ratio in memory other than an octave, folded into
+/// [1, 2).
+pub fn most_familiar_interval(memory: &Memory) -> Option<Frac> {
+    memory.iter()
+          .map(|(note, &familiarity)| (octave_reduce(note.clone()), familiarity))
+          .filter(|(note, _)| *note != Frac(1, 1))
+          .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
+          .map(|(note, _)| note)
+}
+
+/// move the base note to the most familiar ratio other than an octave,
+/// respelling the notes (and, with modulation_moves_memory, the memory)
+/// relative to it. notes that fall off the search grid snap to the
+/// nearest ratio on it; memory entries that do are forgotten. the base
+/// stays within an octave of the configured one. returns the new base note.
+pub fn modulate(base_note: f64, notes: &mut [Frac], memory: &mut Memory, config: &Config) -> f64 {
+    let Frac(a, b) = match most_familiar_interval(memory) {
+        Some(note) => note,
+        None => return base_note,
+    };
+    let by = if base_note * (a as f64) / (b as f64) <= 2_f64 * config.base_note {
+        Frac(a, b)
+    } else {
+        Frac(a, b).checked_div(&Frac(2, 1)).unwrap_or(Frac(a, b))
+    };
+
+    for note in notes.iter_mut() {
+        let Frac(c, d) = note.clone();
+        let Frac(e, f) = by;
+        *note = respell(note, &by, config.ratio_limit).unwrap_or_else(|| {
+            nearest_ratio((c as f64) * (f as f64) / ((d as f64) * (e as f64)), config.ratio_limit)
+        });
+    }
+    if config.modulation_moves_memory {
+        *memory = std::mem::take(memory).into_iter()
+                                        .filter_map(|(note, familiarity)| respell(&note, &by, config.ratio_limit).map(|n| (n, familiarity)))
+                                        .collect();
+    }
+
+    let Frac(e, f) = by;
+    base_note * (e as f64) / (f as f64)
+}
+
+/// the output channels of the machine's own notes and then each voice
+/// in `config`, if any are given or there are more than two channels.
+/// ones left empty get the first two channels, or the only one.
+fn routes(config: &Config) -> Option<Vec<Vec<usize>>> {
+    let outputs = iter::once(&config.outputs).chain(config.voices.iter().map(|voice| &voice.outputs));
+    if config.channels <= 2 && outputs.clone().all(|outputs| outputs.is_empty()) {
+        return None;
+    }
+
+    Some(outputs.map(|outputs| match outputs.is_empty() {
+                    true => (0..config.channels.min(2) as usize).collect(),
+                    false => outputs.clone(),
+                })
+                .collect())
+}
+
+/// what save_state keeps of a session: the notes and the memory, as a
+/// list since json keys can't be ratios.
+#[derive(Serialize, Deserialize)]
+struct State {
+    notes: Vec<Frac>,
+    memory: Vec<(Frac, f64)>,
+}
+
+/// a move from one memory to a profile's, a step further each chord
+/// change.
+struct Morph {
+    from: Memory,
+    to: String,
+    changes: u64,
+    done: u64,
+}
+
+/// the notes, memory and transitions at the start of a chord change,
+/// to roll back to.
+struct Snapshot {
+    notes: Vec<Frac>,
+    memory: Memory,
+    transitions: Transitions,
+}
+
+/// a search for the next chord change's notes running on its own
+/// thread, and what it started from.
+struct Lookahead {
+    notes: Vec<Frac>,
+    memory: Memory,
+    transitions: Transitions,
+    found: Receiver<Vec<Frac>>,
+    ready: Option<Vec<Frac>>,
+}
+
+impl Lookahead {
+    /// start searching on from `notes`, with `memory` and `transitions`
+    /// decayed the way the next chord change will decay them.
+    fn start(notes: &[Frac], memory: &Memory, transitions: &Transitions, judge: &Arc<dyn Judge>, rng: &mut StdRng,
+             config: &Config) -> Lookahead {
+        let (sender, found) = mpsc::sync_channel(1);
+        let (mut searching, mut decayed) = (memory.clone(), transitions.clone());
+        forget(&mut searching, config);
+        decayed.forget(config.decay, config.min_familiarity);
+        let previous = notes.to_owned();
+        let (judge, mut rng, config) = (Arc::clone(judge), StdRng::from_rng(rng).unwrap(), config.clone());
+        thread::spawn(move || {
+            let judge = TransitionJudge {
+                judge: &*judge,
+                previous: &previous,
+                transitions: &decayed,
+                weight: config.transition_weight,
+                ceiling: config.familiarity_ceiling(),
+            };
+            let _ = sender.send(search_notes(&previous, &searching, &judge, &mut rng, &config));
+        });
+
+        Lookahead { notes: notes.to_owned(), memory: memory.clone(), transitions: transitions.clone(), found, ready: None }
+    }
+
+    /// whether the search has finished, keeping what it found.
+    fn done(&mut self) -> bool {
+        match self.found.try_recv() {
+            Ok(notes) => {
+                self.ready = Some(notes);
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => true,
+        }
+    }
+
+    /// whether the search started from the `notes`, `memory` and
+    /// `transitions` the machine has now.
+    fn started_from(&self, notes: &[Frac], memory: &Memory, transitions: &Transitions) -> bool {
+        self.notes == notes && self.memory == *memory && self.transitions == *transitions
+    }
+}
+
+/// the whole machine: composes a noteset every step and renders it as
+/// samples in [-1, 1] at the configured rate.
+pub struct HarmonyMachine {
+    notes: Vec<Frac>,
+    memory: Memory,
+    /// named memories to switch between, and the one playing, which
+    /// lives in `memory` meanwhile.
+    profiles: BTreeMap<String, Memory>,
+    profile: Option<String>,
+    morph: Option<Morph>,
+    voices: Vec<Voice>,
+    base_note: f64,
+    voiced: Vec<f64>,
+    motifs: Motifs,
+    rhythm: Rhythm,
+    transitions: Transitions,
+    /// the last SNAPSHOTS chord changes, oldest first.
+    snapshots: VecDeque<Snapshot>,
+    heatmap: Option<Heatmap>,
+    stats: Stats,
+    last_center: f64,
+    resting: bool,
+    paused: bool,
+    novelty: f64,
+    tension: f64,
+    sounding: Vec<Tone>,
+    releasing: Vec<Release>,
+    air: Air,
+    equalizers: Vec<Equalizer>,
+    /// output channels of the machine's own notes and then of each
+    /// voice, when they're routed rather than mixed to mono or stereo.
+    routes: Option<Vec<Vec<usize>>>,
+    busy: Duration,
+    harmonic_limit: u64,
+    midi: Option<MidiRecorder>,
+    osc: Option<OscSender>,
+    /// the wav file the output is being captured into, if armed.
+    take: Option<(String, WavWriter<io::BufWriter<File>>)>,
+    samples: u64,
+    progress: u64,
+    /// samples in the current step, which the chosen duration stretches
+    /// or shortens.
+    step_length: u64,
+    steps: u64,
+    changes: u64,
+    /// the step to finish on, or the sample whose step to finish on, and
+    /// once it's over, the samples left of the fade out.
+    last_step: Option<u64>,
+    last_sample: Option<u64>,
+    fading: Option<u64>,
+    judge: Arc<dyn Judge>,
+    lookahead: Option<Lookahead>,
+    rng: StdRng,
+    config: Config,
+}
+
+impl HarmonyMachine {
+    pub fn new(config: Config) -> HarmonyMachine {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let (config, memory) = match config.personality {
+            true => personality(&config, &mut rng),
+            false => (config, Memory::new()),
+        };
+        let mut notes = vec![Frac(1, 2), Frac(1, 1), Frac(1, 3), Frac(1, 5), Frac(1, 7)];
+        snap_to_scale(&mut notes, &config.scale, config.candidate_limit);
+        let rooted = config.base_note * progression_root(&config.progression, 0);
+        let voiced = voicing(rooted, &notes, &[], config.register_octaves);
+        let voices: Vec<Voice> = config.voices.iter()
+                                          .map(|settings| Voice::new(settings.clone(), notes.clone(), rooted, &config))
+                                          .collect();
+        let mut sounding = tones(&notes, &voiced, &[], &memory, dynamics(0, 0_f64), 0, &own_part(&config));
+        for (i, voice) in voices.iter().enumerate() {
+            sounding.extend(voice.tones(&memory, &config, dynamics(0, 0_f64), 0).into_iter().map(|tone| Tone { stem: i + 1, ..tone }));
+        }
+        let mut air = Air::new(config.seed, config.air_q);
+        air.tune(&sounding, config.pcm_hz);
+
+        let mut machine = HarmonyMachine {
+            notes,
+            memory,
+            profiles: BTreeMap::new(),
+            profile: None,
+            morph: None,
+            voices,
+            base_note: config.base_note,
+            voiced,
+            motifs: Motifs::new(),
+            rhythm: Rhythm::new(),
+            transitions: Transitions::new(),
+            snapshots: VecDeque::new(),
+            heatmap: None,
+            stats: Stats::new(),
+            last_center: 1_f64,
+            resting: false,
+            paused: false,
+            novelty: 0_f64,
+            tension: 0_f64,
+            sounding,
+            releasing: vec![],
+            air,
+            equalizers: (0..config.channels.max(1)).map(|_| Equalizer::new(&config.eq, config.pcm_hz)).collect(),
+            routes: routes(&config),
+            busy: Duration::new(0, 0),
+            harmonic_limit: MAX_HARMONICS,
+            midi: None,
+            osc: None,
+            take: None,
+            samples: 0,
+            progress: 0,
+            step_length: config.step_samples(),
+            steps: 0,
+            changes: 0,
+            last_step: None,
+            last_sample: None,
+            fading: None,
+            judge: Arc::new(WeightedSum::from_config(&config)),
+            lookahead: None,
+            rng,
+            config,
+        };
+        let mut sounding = mem::take(&mut machine.sounding);
+        machine.pan(&mut sounding);
+        machine.sounding = sounding;
+
+        machine
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// replace the judge step_notes minimizes, by default judge().
+    pub fn set_judge(&mut self, judge: Box<dyn Judge>) {
+        self.judge = Arc::from(judge);
+        self.lookahead = None;
+    }
+
+    /// the notes of the current step.
+    pub fn notes(&self) -> &[Frac] {
+        &self.notes
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// stop composing and fall silent from the next chord change, or
+    /// carry on. memory keeps decaying meanwhile unless the config's
+    /// freeze_decay is set.
+    pub fn pause(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// steer away from `note` for a long time, by AVERSION.
+    pub fn avert(&mut self, note: &Frac) {
+        avert(note, &mut self.memory, AVERSION);
+    }
+
+    /// go back to the notes, memory and transitions from `changes` chord
+    /// changes ago, or as far back as is kept. returns how far it went.
+    pub fn rollback(&mut self, changes: usize) -> usize {
+        let changes = changes.min(self.snapshots.len());
+        if changes == 0 {
+            return 0;
+        }
+        let snapshot = self.snapshots.drain(self.snapshots.len() - changes..).next().unwrap();
+        self.notes = snapshot.notes;
+        self.memory = snapshot.memory;
+        self.transitions = snapshot.transitions;
+
+        changes
+    }
+
+    /// how tense the current chord is, in [0, 1], 0 while resting. see
+    /// analysis::tension.
+    pub fn tension(&self) -> f64 {
+        self.tension
+    }
+
+    /// render the next sample, stepping the composition when a step ends.
+    pub fn next_sample(&mut self) -> f64 {
+        let started = if self.config.cpu_budget > 0_f64 { Some(Instant::now()) } else { None };
+        let sample = sine_waves(&self.sounding, self.samples, self.progress, self.config.pcm_hz)
+                     + release_waves(&self.releasing, self.samples, self.config.pcm_hz)
+                     + self.sub_sample();
+        let aired = sample + self.air_sample();
+        let faded = self.master(aired, 0);
+        self.advance(started);
+
+        faded
+    }
+
+    /// the next left and right samples, with tones placed by config.panning.
+    pub fn next_frame(&mut self) -> (f64, f64) {
+        let started = if self.config.cpu_budget > 0_f64 { Some(Instant::now()) } else { None };
+        let (left, right) = stereo_waves(&self.sounding, self.samples, self.progress, self.config.pcm_hz);
+        let (released_left, released_right) = stereo_release_waves(&self.releasing, self.samples, self.config.pcm_hz);
+        let center = self.sub_sample() + self.air_sample();
+        let frame = (self.master(left + released_left + center, 0), self.master(right + released_right + center, 1));
+        self.advance(started);
+
+        frame
+    }
+
+    /// the next frame with each tone in the output channels its stem is
+    /// routed to, and the sub and air with the machine's own notes.
+    pub fn next_routed(&mut self, frame: &mut [f64]) {
+        let started = if self.config.cpu_budget > 0_f64 { Some(Instant::now()) } else { None };
+        frame.iter_mut().for_each(|sample| *sample = 0_f64);
+        let center = self.sub_sample() + self.air_sample();
+        if let Some(ref routes) = self.routes {
+            routed_waves(&self.sounding, &self.releasing, routes, self.samples, self.progress, self.config.pcm_hz, frame);
+            route(center, 0_f64, &routes[0], frame);
+        }
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            *sample = self.master(*sample, channel);
+        }
+        self.advance(started);
+    }
+
+    /// the sub-oscillator at the current sample, if the config's
+    /// sub_level is on.
+    fn sub_sample(&self) -> f64 {
+        if self.config.sub_level > 0_f64 {
+            if let Some((freq, gain)) = sub_tone(&self.sounding, self.config.sub_level) {
+                return gain * harmonic_wave(&self.config.sub_oscillator, freq, u64::MAX, self.samples, self.config.pcm_hz);
+            }
+        }
+
+        0_f64
+    }
+
+    /// the air layer's next sample, if the config's air_level is on.
+    fn air_sample(&mut self) -> f64 {
+        let level = self.config.air_level;
+        if level > 0_f64 { level * self.air.next_sample() } else { 0_f64 }
+    }
+
+    /// equalize, limit and fade in a mixed sample of `channel`.
+    fn master(&mut self, sample: f64, channel: usize) -> f64 {
+        let equalized = self.equalizers[channel].process(sample);
+        let faded = fade_in(limit(equalized), self.samples, self.config.pcm_hz);
+        match self.fading {
+            Some(left) => fade_out(faded, left, self.config.pcm_hz),
+            None => faded,
+        }
+    }
+
+    /// move on a sample, stepping at the end of each step or fading out
+    /// after the last, and count the time since `started` against
+    /// the config's cpu_budget.
+    fn advance(&mut self, started: Option<Instant>) {
+        self.samples = self.samples.wrapping_add(1);
+        self.progress += 1;
+        if let Some(ref mut left) = self.fading {
+            *left = left.saturating_sub(1);
+        } else if self.progress >= self.step_length {
+            if self.on_last_step() {
+                self.fading = Some(self.fade_samples());
+            } else {
+                self.progress = 0;
+                self.step();
+            }
+        }
+        if let Some(started) = started {
+            self.busy += started.elapsed();
+        }
+    }
+
+    /// finish the step under way and `steps` more, then fade out over
+    /// FADE_OUT_SECS and fall silent. an earlier finish already asked for
+    /// stands.
+    pub fn finish_after(&mut self, steps: u64) {
+        let last = self.steps + steps;
+        self.last_step = Some(self.last_step.map_or(last, |earlier| earlier.min(last)));
+    }
+
+    /// finish the step that sample number `sample` falls in, counting
+    /// from the first sample rendered, then fade out like finish_after.
+    pub fn finish_at(&mut self, sample: u64) {
+        self.last_sample = Some(self.last_sample.map_or(sample, |earlier| earlier.min(sample)));
+    }
+
+    /// whether the step under way is the last before fading out.
+    fn on_last_step(&self) -> bool {
+        let step_end = self.samples + self.step_length.saturating_sub(self.progress);
+        self.last_step.is_some_and(|last| self.steps >= last) || self.last_sample.is_some_and(|last| step_end >= last)
+    }
+
+    /// samples until the machine has finished and faded out, once it's
+    /// on its last step; 0 when it has.
+    pub fn samples_left(&self) -> Option<u64> {
+        match self.fading {
+            Some(left) => Some(left),
+            None if self.on_last_step() => {
+                Some(self.step_length.saturating_sub(self.progress) + self.fade_samples())
+            }
+            None => None,
+        }
+    }
+
+    fn fade_samples(&self) -> u64 {
+        (FADE_OUT_SECS * self.config.pcm_hz as f64).round() as u64
+    }
+
+    /// fill `chunk` with the next samples, as interleaved frames of
+    /// config.channels.
+    pub fn next_chunk(&mut self, chunk: &mut [f64]) {
+        if self.routes.is_some() {
+            for frame in chunk.chunks_mut(self.config.channels as usize) {
+                self.next_routed(frame);
+            }
+        } else if self.config.channels == 2 {
+            for frame in chunk.chunks_mut(2) {
+                let (left, right) = self.next_frame();
+                frame[0] = left;
+                if let Some(sample) = frame.get_mut(1) {
+                    *sample = right;
+                }
+            }
+        } else {
+            for sample in chunk.iter_mut() {
+                *sample = self.next_sample();
+            }
+        }
+        if self.take.is_some() {
+            let scale = i16::MAX as f64 - 1_f64;
+            let samples: Vec<i16> = chunk.iter().map(|&sample| (sample * scale) as i16).collect();
+            self.capture(&samples);
+        }
+    }
+
+    /// fill `buffer` with the next samples as 16 bit pcm, interleaved
+    /// like next_chunk.
+    pub fn render_into(&mut self, buffer: &mut [i16]) {
+        let scale = i16::MAX as f64 - 1_f64;
+        if self.routes.is_some() {
+            let mut frame = vec![0_f64; self.config.channels as usize];
+            for out in buffer.chunks_mut(frame.len()) {
+                self.next_routed(&mut frame);
+                for (sample, &routed) in out.iter_mut().zip(&frame) {
+                    *sample = (routed * scale) as i16;
+                }
+            }
+        } else if self.config.channels == 2 {
+            for frame in buffer.chunks_mut(2) {
+                let (left, right) = self.next_frame();
+                frame[0] = (left * scale) as i16;
+                if let Some(sample) = frame.get_mut(1) {
+                    *sample = (right * scale) as i16;
+                }
+            }
+        } else {
+            for sample in buffer.iter_mut() {
+                *sample = (self.next_sample() * scale) as i16;
+            }
+        }
+        self.capture(buffer);
+    }
+
+    /// send the notes of every step from here on to `target`, a host and
+    /// port, as open sound control. see osc::OscSender::send.
+    pub fn send_osc(&mut self, target: &str) -> io::Result<()> {
+        self.osc = Some(OscSender::new(target)?);
+        self.send_step();
+
+        Ok(())
+    }
+
+    /// send the step under way to the osc target, if there is one, with
+    /// harmony scored by the configured harmony_metric.
+    fn send_step(&self) {
+        if let Some(ref osc) = self.osc {
+            // a receiver that isn't listening yet shouldn't stop the music
+            let _ = match self.resting {
+                true => osc.send(self.steps, &[], &[], &[]),
+                false => {
+                    let harmony = harmony_judge(&self.config).score(&self.notes, &self.memory);
+                    osc.send(self.steps, &self.voiced, &self.notes, &[harmony, self.novelty, self.tension])
+                }
+            };
+        }
+    }
+
+    /// capture the output from here on into a wav file at `path`, as a
+    /// take, finishing the one under way first. fails with AlreadyExists
+    /// rather than overwrite a file at `path`.
+    pub fn arm(&mut self, path: &str) -> io::Result<()> {
+        self.disarm()?;
+        let file = io::BufWriter::new(OpenOptions::new().write(true).create_new(true).open(path)?);
+        self.take = Some((path.to_owned(), WavWriter::new(file, self.config.pcm_hz as u32, self.config.channels)?));
+
+        Ok(())
+    }
+
+    /// stop capturing and finish the take's file. returns its path, none
+    /// if there was no take.
+    pub fn disarm(&mut self) -> io::Result<Option<String>> {
+        match self.take.take() {
+            Some((path, wav)) => {
+                wav.finish()?;
+                Ok(Some(path))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// add rendered samples to the take, if armed. a take that can't be
+    /// written is dropped so the output carries on.
+    fn capture(&mut self, samples: &[i16]) {
+        if let Some((ref path, ref mut wav)) = self.take {
+            let mut bytes = vec![0_u8; samples.len() * 2];
+            for (pair, &sample) in bytes.chunks_mut(2).zip(samples) {
+                pair.copy_from_slice(&sample.to_le_bytes());
+            }
+            if let Err(e) = wav.write_all(&bytes) {
+                eprintln!("couldn't record to {}: {}", path, e);
+                self.take = None;
+            }
+        }
+    }
+
+    /// start keeping the notes of every step from here on for write_midi.
+    pub fn record_midi(&mut self) {
+        let mut recorder = MidiRecorder::new(self.samples, self.config.step_samples(), self.config.steps_per_sec);
+        recorder.record(self.samples, &self.all_voiced(), velocity(dynamics(self.steps, self.novelty)));
+        self.midi = Some(recorder);
+    }
+
+    /// write the notes recorded since record_midi as a midi file.
+    pub fn write_midi(&self, path: &str) -> io::Result<()> {
+        match self.midi {
+            Some(ref recorder) => recorder.write(path),
+            None => Err(io::Error::other("midi recording was never started")),
+        }
+    }
+
+    /// steps rendered so far, counting the one under way.
+    pub fn steps(&self) -> u64 {
+        self.steps + 1
+    }
+
+    /// chord changes so far, not counting the first chord.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// print the session summary to stderr.
+    pub fn print_summary(&self) {
+        self.stats.print(self.steps(), self.samples, self.config.pcm_hz);
+    }
+
+    /// save the current notes and memory as json, for resume_state.
+    pub fn save_state(&self, path: &str) -> io::Result<()> {
+        let state = State {
+            notes: self.notes.clone(),
+            memory: self.memory.iter().map(|(note, &familiarity)| (note.clone(), familiarity)).collect(),
+        };
+        serde_json::to_writer(io::BufWriter::new(File::create(path)?), &state)?;
+
+        Ok(())
+    }
+
+    /// load the memory in a file written by save_state as profile `name`,
+    /// to switch to or blend in later.
+    pub fn load_profile(&mut self, name: &str, path: &str) -> io::Result<()> {
+        let state: State = serde_json::from_reader(io::BufReader::new(File::open(path)?))?;
+        if state.memory.iter().any(|&(Frac(a, b), _)| a == 0 || b == 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "remembered notes must be nonzero ratios"));
+        }
+        self.profiles.insert(name.to_owned(), state.memory.into_iter().collect());
+
+        Ok(())
+    }
+
+    /// names of the loaded profiles, and the one playing if any.
+    pub fn profiles(&self) -> (Vec<&str>, Option<&str>) {
+        (self.profiles.keys().map(|name| name.as_str()).collect(), self.profile.as_deref())
+    }
+
+    /// play from profile `name`'s memory. what the profile that was
+    /// playing learned is kept in it for switching back.
+    pub fn switch_profile(&mut self, name: &str) -> Result<(), String> {
+        if self.profile.as_deref() == Some(name) {
+            return Ok(());
+        }
+        let memory = self.profiles.get(name).cloned().ok_or_else(|| format!("no profile {}", name))?;
+        self.morph = None;
+        if let Some(playing) = self.profile.take() {
+            self.profiles.insert(playing, mem::replace(&mut self.memory, memory));
+        } else {
+            self.memory = memory;
+        }
+        self.profile = Some(name.to_owned());
+
+        Ok(())
+    }
+
+    /// play from a weighted blend of profiles, which leaves them as they
+    /// are.
+    pub fn blend_profiles(&mut self, weights: &[(&str, f64)]) -> Result<(), String> {
+        let mut memories = vec![];
+        for &(name, weight) in weights {
+            memories.push((weight, self.profiles.get(name).ok_or_else(|| format!("no profile {}", name))?));
+        }
+        let blended = blend(&memories);
+        self.morph = None;
+        if let Some(playing) = self.profile.take() {
+            self.profiles.insert(playing, mem::replace(&mut self.memory, blended));
+        } else {
+            self.memory = blended;
+        }
+
+        Ok(())
+    }
+
+    /// move from profile `from`'s memory to profile `to`'s over `changes`
+    /// chord changes, then play on from `to`. what is learned on the way
+    /// is let go.
+    pub fn morph_profiles(&mut self, from: &str, to: &str, changes: u64) -> Result<(), String> {
+        let memory = self.profiles.get(from).cloned().ok_or_else(|| format!("no profile {}", from))?;
+        if !self.profiles.contains_key(to) {
+            return Err(format!("no profile {}", to));
+        }
+        if changes == 0 {
+            return self.switch_profile(to);
+        }
+        if let Some(playing) = self.profile.take() {
+            self.profiles.insert(playing, mem::replace(&mut self.memory, memory.clone()));
+        } else {
+            self.memory = memory.clone();
+        }
+        self.morph = Some(Morph { from: memory, to: to.to_owned(), changes, done: 0 });
+
+        Ok(())
+    }
+
+    /// take the next step of a morph, if one is under way.
+    fn morph_step(&mut self) {
+        let finished = match self.morph {
+            Some(ref mut morph) => {
+                morph.done += 1;
+                let t = (morph.done as f64) / (morph.changes as f64);
+                self.memory = interpolate(&morph.from, &self.profiles[&morph.to], t);
+                morph.done >= morph.changes
+            }
+            None => return,
+        };
+        if finished {
+            let to = self.morph.take().unwrap().to;
+            self.memory = self.profiles[&to].clone();
+            self.profile = Some(to);
+        }
+    }
+
+    /// start from a memory learned from the notes in a midi file, or in a
+    /// text file of ratios like "3/2" separated by spaces or lines, with
+    /// # starting a comment. see learn.
+    pub fn import_memory(&mut self, path: &str) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let notes: Vec<Frac> = if bytes.starts_with(b"MThd") {
+            midi::read_notes(&bytes)?.into_iter()
+                                     .map(|freq| nearest_ratio(freq / self.base_note, self.config.ratio_limit))
+                                     .collect()
+        } else {
+            let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+            let text = String::from_utf8(bytes).map_err(|e| invalid(e.to_string()))?;
+            let ratios = text.lines().flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace());
+            ratios.map(|ratio| ratio.parse().map(simplify)).collect::<Result<_, _>>().map_err(invalid)?
+        };
+        self.memory = learn(&notes, &self.config);
+
+        Ok(())
+    }
+
+    /// pick up the notes and memory saved by save_state, so what was
+    /// learned survives a restart.
+    pub fn resume_state(&mut self, path: &str) -> io::Result<()> {
+        let state: State = serde_json::from_reader(io::BufReader::new(File::open(path)?))?;
+        if state.notes.is_empty() || state.notes.iter().any(|&Frac(a, b)| a == 0 || b == 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "saved notes must be nonzero ratios"));
+        }
+        if state.memory.iter().any(|&(Frac(a, b), _)| a == 0 || b == 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "remembered notes must be nonzero ratios"));
+        }
+
+        self.notes = state.notes;
+        snap_to_scale(&mut self.notes, &self.config.scale, self.config.candidate_limit);
+        self.memory = state.memory.into_iter().collect();
+        self.voiced = voicing(self.rooted_base(), &self.notes, &[], self.config.register_octaves);
+        let level = dynamics(self.steps, self.novelty);
+        let mut sounding = tones(&self.notes, &self.voiced, &[], &self.memory, level, self.steps, &own_part(&self.config));
+        self.pan(&mut sounding);
+        self.sounding = sounding;
+        self.air.tune(&self.sounding, self.config.pcm_hz);
+
+        Ok(())
+    }
+
+    /// start keeping the familiarity of every ratio at each chord change
+    /// from here on for write_heatmap.
+    pub fn record_heatmap(&mut self) {
+        self.heatmap = Some(Heatmap::new(self.config.ratio_limit));
+    }
+
+    /// write the familiarity heatmap recorded since record_heatmap as a
+    /// pgm image.
+    pub fn write_heatmap(&self, path: &str) -> io::Result<()> {
+        match self.heatmap {
+            Some(ref heatmap) => heatmap.write(path),
+            None => Err(io::Error::other("heatmap recording was never started")),
+        }
+    }
+
+    /// move the harmonic cap by how long the last step took to render
+    /// against how long it plays.
+    fn govern(&mut self) {
+        let budget = self.config.cpu_budget;
+        if budget <= 0_f64 {
+            return;
+        }
+
+        let busy = self.busy.as_secs() as f64 + (self.busy.subsec_nanos() as f64) / 1e9_f64;
+        let load = busy * (self.config.steps_per_sec as f64);
+        self.busy = Duration::new(0, 0);
+        if load > budget && self.harmonic_limit > 1 {
+            self.harmonic_limit -= 1;
+        } else if load < budget / 2_f64 && self.harmonic_limit < MAX_HARMONICS {
+            self.harmonic_limit += 1;
+        }
+    }
+
+    /// the frequency the notes are composed over: the base note moved to
+    /// the progression's current root.
+    fn rooted_base(&self) -> f64 {
+        self.base_note * progression_root(&self.config.progression, self.changes)
+    }
+
+    /// the frequencies of the machine's notes and every voice's, leaving
+    /// out whoever is resting.
+    fn all_voiced(&self) -> Vec<f64> {
+        let mut voiced = if self.resting { vec![] } else { self.voiced.clone() };
+        for voice in &self.voices {
+            voiced.extend_from_slice(voice.voiced());
+        }
+
+        voiced
+    }
+
+    /// the (from, to) frequencies of a glide for this step, if the only
+    /// note that changed moved by a simple enough interval and the dice
+    /// agree.
+    fn glide(&mut self, previous: &[Frac], previous_voiced: &[f64]) -> Option<(f64, f64)> {
+        let chance = self.config.glide_chance;
+        if chance <= 0_f64 || self.resting || self.sounding.is_empty() {
+            return None;
+        }
+
+        let left: Vec<usize> = (0..previous.len()).filter(|&i| !self.notes.contains(&previous[i])).collect();
+        let arrived: Vec<usize> = (0..self.notes.len()).filter(|&i| !previous.contains(&self.notes[i])).collect();
+        if left.len() != 1 || arrived.len() != 1 {
+            return None;
+        }
+        let height = self.notes[arrived[0]].height_over(&previous[left[0]]);
+        if math::log2(height) > self.config.glide_max_tenney || self.rng.gen::<f64>() >= chance {
+            return None;
+        }
+
+        let (from, to) = (*previous_voiced.get(left[0])?, *self.voiced.get(arrived[0])?);
+        if from == to || cents(to / from).abs() > self.config.glide_max_cents { None } else { Some((from, to)) }
+    }
+
+    /// place tones across the stereo field by config.panning. mark_onsets puts
+    /// held ones back where they were.
+    fn pan(&mut self, sounding: &mut [Tone]) {
+        if self.config.channels < 2 {
+            return;
+        }
+        let rooted = self.rooted_base();
+        for tone in sounding {
+            tone.pan = self.config.pan_width * match self.config.panning {
+                Panning::ByPitch => (math::log2(tone.freq / rooted) / self.config.pan_octaves).clamp(-1_f64, 1_f64),
+                Panning::Random => self.rng.gen_range(-1_f64..1_f64),
+            };
+        }
+    }
+
+    /// decorate tones held from the last step, each with the ornament
+    /// settings of the machine or the voice it belongs to.
+    fn ornament(&mut self, sounding: &mut [Tone]) {
+        let (config, voices, rng) = (&self.config, &self.voices, &mut self.rng);
+        for tone in sounding {
+            let (chance, kind, &Frac(a, b), rate_hz) = match tone.stem {
+                0 => (config.ornament_chance, config.ornament, &config.ornament_neighbor, config.ornament_rate_hz),
+                stem => {
+                    let settings = voices[stem - 1].settings();
+                    (settings.ornament_chance, settings.ornament, &settings.ornament_neighbor, settings.ornament_rate_hz)
+                }
+            };
+            if chance <= 0_f64 || tone.onset || tone.glide.is_some() || rng.gen::<f64>() >= chance {
+                continue;
+            }
+            let mut ornament = Ornament { kind, neighbor: a as f64 / b as f64, unit: 1_f64 };
+            ornament.unit = (config.pcm_hz as f64 / rate_hz).min(self.step_length as f64 / ornament.units());
+            tone.ornament = Some(ornament);
+        }
+    }
+
+    fn step(&mut self) {
+        self.govern();
+        self.steps += 1;
+        let step = self.steps;
+        let previous = self.notes.clone();
+        let previous_voiced = self.voiced.clone();
+        let previous_length = self.step_length;
+        let change = step.is_multiple_of(self.config.steps_per_change);
+        if change && self.paused {
+            self.resting = true;
+            if !self.config.freeze_decay {
+                forget(&mut self.memory, &self.config);
+                self.transitions.forget(self.config.decay, self.config.min_familiarity);
+            }
+        }
+        // a search running ahead that isn't done holds the chord a step
+        // longer rather than holding up the audio
+        let waiting = change && !self.paused && self.lookahead.as_mut().is_some_and(|ahead| !ahead.done());
+        if change && !self.paused && !waiting {
+            let ahead = self.lookahead.take().filter(|ahead| ahead.started_from(&previous, &self.memory, &self.transitions));
+            if self.snapshots.len() == SNAPSHOTS {
+                self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(Snapshot {
+                notes: previous.clone(),
+                memory: self.memory.clone(),
+                transitions: self.transitions.clone(),
+            });
+            if !self.config.durations.is_empty() {
+                let Frac(a, b) = self.rhythm.next(&self.config.durations);
+                self.step_length = (self.config.step_samples() as f64 * a as f64 / b as f64).round().max(1_f64) as u64;
+            }
+            let phrase = step / PHRASE_STEPS;
+            let phrases = self.config.modulation_phrases;
+            let modulating = phrases > 0 && step.is_multiple_of(PHRASE_STEPS) && phrase.is_multiple_of(phrases);
+            if modulating {
+                self.base_note = modulate(self.base_note, &mut self.notes, &mut self.memory, &self.config);
+            }
+            let frozen = self.config.freeze_decay && self.resting;
+            // the search ahead only decayed the way forget does
+            let ahead = ahead.filter(|_| !modulating && !frozen && self.morph.is_none());
+            if self.morph.is_some() {
+                self.morph_step();
+            } else if !frozen {
+                forget(&mut self.memory, &self.config);
+            }
+            if !frozen {
+                self.transitions.forget(self.config.decay, self.config.min_familiarity);
+            }
+            if let Some(recalled) = self.motifs.next(&self.memory, self.config.ratio_limit) {
+                self.notes = recalled;
+                self.resting = false;
+            } else {
+                self.notes = match ahead.and_then(|ahead| ahead.ready) {
+                    Some(notes) => notes,
+                    None => {
+                        let judge = TransitionJudge {
+                            judge: &*self.judge,
+                            previous: &previous,
+                            transitions: &self.transitions,
+                            weight: self.config.transition_weight,
+                            ceiling: self.config.familiarity_ceiling(),
+                        };
+                        search_notes(&previous, &self.memory, &judge, &mut self.rng, &self.config)
+                    }
+                };
+                let score = self.judge.score(&self.notes, &self.memory);
+                self.resting = score > self.config.tacet_score;
+                if !self.resting {
+                    self.motifs.record(&self.notes, score);
+                }
+            }
+            snap_to_scale(&mut self.notes, &self.config.scale, self.config.candidate_limit);
+            self.novelty = judge_novelty(&self.notes, &self.memory, self.config.target_familiarity, self.config.familiarity_ceiling());
+            self.tension = if self.resting { 0_f64 } else { tension(&self.notes, &self.memory, self.config.candidate_limit) };
+            if let Some(ref mut heatmap) = self.heatmap {
+                heatmap.record(&self.memory);
+            }
+            self.stats.record(&self.notes, &self.memory, self.resting, &self.config);
+            self.changes += 1;
+            let report = self.config.center_report_changes;
+            if report > 0 && self.changes.is_multiple_of(report) {
+                let center = tonal_center(&self.memory);
+                let Frac(a, b) = nearest_ratio(center, self.config.ratio_limit);
+                eprintln!("center: {}/{} ({:+.0} cents), drift {:+.0} cents",
+                          a, b, cents(center), cents(center / self.last_center));
+                self.last_center = center;
+            }
+            if !self.resting {
+                remember(&self.notes, &mut self.memory, &self.config);
+                if self.config.transition_weight != 0_f64 {
+                    self.transitions.remember(&previous, &self.notes, self.config.familiarity_increment,
+                                              self.config.max_familiarity);
+                }
+                self.voiced = voicing(self.rooted_base(), &self.notes, &self.voiced, self.config.register_octaves);
+                if self.config.log_intervals {
+                    let (intervals, tenney, rough) = interval_content(&self.notes, &self.voiced);
+                    let spelled: Vec<String> = intervals.iter().map(|&Frac(a, b)| format!("{}/{}", a, b)).collect();
+                    eprintln!("intervals\t{}\t{}\t{:.3}\t{:.4}", step, spelled.join(" "), tenney, rough);
+                }
+            }
+            let rooted = self.rooted_base();
+            for voice in &mut self.voices {
+                let leader = if self.resting { None } else { Some(&self.notes[..]) };
+                voice.step(leader, &mut self.memory, &*self.judge, &mut self.rng, &self.config, rooted);
+            }
+            if self.config.search_ahead {
+                self.lookahead = Some(Lookahead::start(&self.notes, &self.memory, &self.transitions, &self.judge,
+                                                       &mut self.rng, &self.config));
+            }
+        }
+        let level = dynamics(step, self.novelty);
+        let mut sounding = if self.resting {
+            vec![]
+        } else {
+            tones(&self.notes, &self.voiced, &previous, &self.memory, level, step, &own_part(&self.config))
+        };
+        let voices = if self.paused { &[][..] } else { &self.voices[..] };
+        for (i, voice) in voices.iter().enumerate() {
+            let voice_tones: Vec<Tone> = voice.tones(&self.memory, &self.config, level, step)
+                                              .into_iter()
+                                              .map(|tone| Tone { stem: i + 1, ..tone })
+                                              .collect();
+            sounding.extend(voice_tones);
+        }
+        if let Some((from, to)) = self.glide(&previous, &previous_voiced) {
+            if let Some(tone) = sounding.iter_mut().find(|tone| tone.freq == to) {
+                tone.glide = Some(Glide { from, start: self.samples, samples: self.step_length });
+            }
+        }
+        self.pan(&mut sounding);
+        mark_onsets(&mut sounding, &self.sounding, self.samples);
+        self.ornament(&mut sounding);
+        let (start, pcm_hz) = (self.samples, self.config.pcm_hz);
+        let stopped = mem::replace(&mut self.sounding, sounding);
+        self.releasing.retain(|release| {
+            release.tone.envelope.release(release.level, start.wrapping_sub(release.start) as f64 / pcm_hz as f64) > 0_f64
+        });
+        self.releasing.extend(releases(stopped, &self.sounding, start, previous_length, pcm_hz));
+        if self.midi.is_some() {
+            let voiced = self.all_voiced();
+            if let Some(ref mut recorder) = self.midi {
+                recorder.record(start, &voiced, velocity(level));
+            }
+        }
+        self.send_step();
+        if self.harmonic_limit < MAX_HARMONICS {
+            for tone in &mut self.sounding {
+                tone.harmonics = tone.harmonics.min(self.harmonic_limit);
+            }
+        }
+        self.air.tune(&self.sounding, self.config.pcm_hz);
+    }
+}
+
+/// what the machine's own tones are made with.
+fn own_part(config: &Config) -> Part<'_> {
+    Part { oscillator: None, note_gates: &config.note_gates, doublings: &config.doublings, config }
+}
+
+/// midi velocity for a dynamics level.
+fn velocity(level: f64) -> u8 {
+    (level * 100_f64).round().clamp(1_f64, 127_f64) as u8
+}
+
+impl Default for HarmonyMachine {
+    fn default() -> HarmonyMachine {
+        HarmonyMachine::new(Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ratios() {
+        assert_eq!("3/2".parse::<Frac>(), Ok(Frac(3, 2)));
+        assert_eq!("6/4".parse::<Frac>(), Ok(Frac(3, 2)));
+        assert_eq!("5".parse::<Frac>(), Ok(Frac(5, 1)));
+        assert_eq!(" 7 / 4 ".parse::<Frac>(), Ok(Frac(7, 4)));
+        assert_eq!("18446744073709551615/1".parse::<Frac>(), Ok(Frac(u64::MAX, 1)));
+    }
+
+    #[test]
+    fn rejects_malformed_ratios() {
+        for s in &["", "/", "3/", "/2", "a/b", "3/2/1", "-3/2", "1.5", "3:2", "18446744073709551616/1"] {
+            assert!(s.parse::<Frac>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn rejects_zero_terms() {
+        for s in &["0", "0/1", "1/0", "0/0"] {
+            assert!(s.parse::<Frac>().is_err(), "{:?} parsed", s);
+        }
+        assert_eq!(Frac(0, 1).checked_mul(&Frac(3, 2)), None);
+        assert_eq!(Frac(3, 2).checked_mul(&Frac(1, 0)), None);
+        assert_eq!(Frac(0, 1).checked_div(&Frac(0, 1)), None);
+        assert_eq!(Frac(3, 2).checked_div(&Frac(0, 1)), None);
+    }
+
+    #[test]
+    fn multiplies_in_lowest_terms() {
+        assert_eq!(Frac(3, 2).checked_mul(&Frac(4, 3)), Some(Frac(2, 1)));
+        assert_eq!(Frac(3, 2).checked_div(&Frac(9, 8)), Some(Frac(4, 3)));
+        assert_eq!(Frac(5, 4).height_over(&Frac(1, 1)), 20_f64);
+        assert_eq!(Frac(3, 2).height_over(&Frac(3, 2)), 1_f64);
+    }
+
+    #[test]
+    fn overflow_is_none_not_a_wrap() {
+        assert_eq!(Frac(u64::MAX, 1).checked_mul(&Frac(2, 1)), None);
+        assert_eq!(Frac(1, u64::MAX).checked_mul(&Frac(1, 2)), None);
+        assert_eq!(Frac(u64::MAX, 1).checked_div(&Frac(1, 2)), None);
+        // terms past u64 in the middle that cancel back down still fit
+        assert_eq!(Frac(u64::MAX, 3).checked_mul(&Frac(3, u64::MAX)), Some(Frac(1, 1)));
+        assert_eq!(Frac(u64::MAX, 1).checked_div(&Frac(u64::MAX, 2)), Some(Frac(2, 1)));
+    }
+
+    #[test]
+    fn heights_past_u64_stay_finite() {
+        let height = Frac(u64::MAX, 1).height_over(&Frac(1, u64::MAX));
+        assert!(height.is_finite());
+        assert!(height > u64::MAX as f64);
+    }
+
+    #[test]
+    fn folds_huge_terms_into_range() {
+        for note in [Frac(u64::MAX, 1), Frac(1, u64::MAX), Frac(u64::MAX, u64::MAX - 1)] {
+            let Frac(a, b) = fold(note.clone());
+            let (a, b) = (a as u128, b as u128);
+            assert!(a > 0 && b > 0);
+            assert!(a <= 12 * b && 12 * a >= b, "{:?} folded to {}/{}", note, a, b);
+            let Frac(a, b) = octave_reduce(note.clone());
+            let (a, b) = (a as u128, b as u128);
+            assert!(a >= b && a < 2 * b, "{:?} reduced to {}/{}", note, a, b);
+        }
+    }
+
+    #[test]
+    fn random_candidates_follow_the_seed() {
+        let config = Config { candidates: vec![Candidates::Random], random_candidates: 64, ..Config::default() };
+        let notes = [Frac(1, 1), Frac(3, 2)];
+        let draw = |seed| candidates(&notes, &Memory::new(), &mut StdRng::seed_from_u64(seed), &config);
+        let drawn = draw(7);
+        assert_eq!(drawn, draw(7));
+        assert_ne!(drawn, draw(8));
+        assert!(!drawn.is_empty());
+        for note in &drawn {
+            let &Frac(a, b) = note;
+            assert!(a <= config.candidate_limit && b <= config.candidate_limit, "{}/{} past the limit", a, b);
+            assert!(!notes.contains(note));
+        }
+    }
+
+    /// render a machine asked to stop after `steps` steps, the way
+    /// main does for --steps, and return it with the samples rendered.
+    fn render_steps(config: Config, steps: u64) -> (HarmonyMachine, u64) {
+        let mut machine = HarmonyMachine::new(config);
+        machine.finish_after(steps - 1);
+        let mut rendered = 0;
+        let mut buffer = [0_i16; 256];
+        loop {
+            let len = match machine.samples_left() {
+                Some(0) => break,
+                Some(left) => buffer.len().min(left as usize),
+                None => buffer.len(),
+            };
+            machine.render_into(&mut buffer[..len]);
+            rendered += len as u64;
+        }
+
+        (machine, rendered)
+    }
+
+    #[test]
+    fn counts_the_steps_it_rendered() {
+        let config = Config { pcm_hz: 800, ..Config::default() };
+        let (machine, rendered) = render_steps(config.clone(), 8);
+        assert_eq!(machine.steps(), 8);
+        assert_eq!(machine.changes(), 7);
+        assert_eq!(rendered, 8 * config.step_samples() + machine.fade_samples());
+
+        let (machine, _) = render_steps(Config { steps_per_change: 2, ..config }, 8);
+        assert_eq!(machine.steps(), 8);
+        assert_eq!(machine.changes(), 3);
+    }
+}
@@ -1,166 +1,734 @@
 extern crate byteorder;
+extern crate clap;
+extern crate ctrlc;
+extern crate harmonymachine;
+extern crate toml;
 
-use std::collections::HashMap;
-use std::io::Write;
-use byteorder::{LittleEndian, WriteBytesExt};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use byteorder::{ByteOrder, LittleEndian};
+use clap::Parser;
+use harmonymachine::{Candidates, Config, Frac, HarmonyMachine, HarmonyMetric, Search};
+use harmonymachine::synth::{Interaction, Masking, OrnamentKind, Panning};
+use harmonymachine::wav::WavWriter;
 
-#[derive(Hash, Eq, PartialEq, Clone, Debug)]
-struct Frac(u64, u64);
+type Endianness = LittleEndian;
+/// frames rendered and written at a time.
+static BUFFER_FRAMES: usize = 4096;
+/// print a summary of the session to stderr when the stream ends.
+static PRINT_SUMMARY: bool = true;
 
-type Memory = HashMap<Frac, f64>;
+/// generates music from simple integer ratios, avoiding repetition.
+///
+/// with neither --out nor --play, raw signed 16 bit little-endian pcm
+/// goes to stdout, interleaved left and right when stereo.
+///
+/// lines on stdin steer it while it plays: "profile NAME" switches
+/// profile, "blend NAME=WEIGHT NAME=WEIGHT..." plays from a mix of them,
+/// "morph FROM TO CHANGES" moves from one to another gradually,
+/// "avert [RATIO...]" steers away from the ratios given, or from the
+/// notes playing, "rollback [CHANGES]" goes back to the notes and
+/// memory of that many chord changes ago, "pause" and "play" stop and
+/// start composing, and "arm [DIR]" and "disarm" start and stop
+/// recording what's playing to a timestamped wav file per take.
+#[derive(Parser)]
+struct Args {
+    /// write a wav file instead of raw pcm to stdout.
+    #[arg(long, value_name = "FILE.wav", conflicts_with = "play")]
+    out: Option<PathBuf>,
+    /// play through the default sound card. needs the playback feature.
+    #[arg(long, hide = !cfg!(feature = "playback"))]
+    play: bool,
+    /// also write the chosen notes as a midi file, pitch bent to their
+    /// ratios.
+    #[arg(long, value_name = "FILE.mid")]
+    midi: Option<PathBuf>,
+    /// also write how familiar every ratio was at each chord change as a
+    /// pgm image, one column per change.
+    #[arg(long, value_name = "FILE.pgm")]
+    heatmap: Option<PathBuf>,
+    /// also send each step's notes and scores as open sound control to
+    /// this host and port, e.g. 127.0.0.1:57120.
+    #[arg(long, value_name = "HOST:PORT")]
+    osc: Option<String>,
+    /// keep no audio, only running the machine in real time, for --osc.
+    #[arg(long, requires = "osc", conflicts_with_all = ["out", "play"])]
+    no_audio: bool,
+    /// load notes and memory from this json file if it exists, and save
+    /// them back to it when the stream ends.
+    #[arg(long, value_name = "FILE.json")]
+    resume: Option<PathBuf>,
+    /// start from a memory learned from the notes in a midi file, or a
+    /// text file of ratios. --resume wins if its file exists.
+    #[arg(long, value_name = "FILE")]
+    import: Option<PathBuf>,
+    /// stop after the step playing at this many seconds, fading it out.
+    #[arg(long, value_name = "SECONDS")]
+    duration: Option<f64>,
+    /// stop after this many steps, fading out the last.
+    #[arg(long)]
+    steps: Option<u64>,
+    /// load a memory saved with --resume as a named profile. repeat for
+    /// more; the first one plays from the start.
+    #[arg(long, value_name = "NAME=FILE.json")]
+    profile: Vec<String>,
+    /// toml file with any of the settings below. flags win over it.
+    #[arg(long, value_name = "FILE.toml")]
+    config: Option<PathBuf>,
+    /// output sample rate in Hz.
+    #[arg(long)]
+    pcm_hz: Option<u64>,
+    /// 1 for mono, 2 for stereo with the notes panned, or more to route
+    /// the machine and its voices to.
+    #[arg(long)]
+    channels: Option<u16>,
+    /// output channels the machine's own notes play through, e.g. 0,1.
+    #[arg(long, value_delimiter = ',')]
+    outputs: Option<Vec<usize>>,
+    /// how stereo places tones: by_pitch, low to high, or random.
+    #[arg(long)]
+    panning: Option<Panning>,
+    /// how far from the middle tones may pan, 1 being hard left or
+    /// right.
+    #[arg(long, value_name = "WIDTH")]
+    pan_width: Option<f64>,
+    /// octaves from the base note at which by_pitch panning reaches
+    /// pan_width.
+    #[arg(long, value_name = "OCTAVES")]
+    pan_octaves: Option<f64>,
+    /// with --play, mix the default input device in at this gain.
+    #[arg(long, value_name = "GAIN")]
+    passthrough: Option<f64>,
+    /// notesets per second.
+    #[arg(long)]
+    steps_per_sec: Option<u64>,
+    /// steps each chord lasts, re-sounded on each.
+    #[arg(long)]
+    steps_per_change: Option<u64>,
+    /// frequency in Hz of the 1/1 ratio.
+    #[arg(long)]
+    base_note: Option<f64>,
+    /// what each remembered familiarity is multiplied by every chord
+    /// change.
+    #[arg(long)]
+    decay: Option<f64>,
+    /// familiarity added to a note each time it is played.
+    #[arg(long)]
+    familiarity_increment: Option<f64>,
+    /// familiarity that remembering levels off toward, and that the
+    /// judges weigh familiarity as a share of.
+    #[arg(long)]
+    max_familiarity: Option<f64>,
+    /// familiarities that decay closer to unfamiliar than this are
+    /// forgotten.
+    #[arg(long)]
+    min_familiarity: Option<f64>,
+    /// keep memory from decaying through rests and pauses.
+    #[arg(long)]
+    freeze_decay: bool,
+    /// average familiarity the search steers toward.
+    #[arg(long)]
+    target_familiarity: Option<f64>,
+    /// weight of harmony in the judge.
+    #[arg(long)]
+    harmony_weight: Option<f64>,
+    /// weight of novelty in the judge.
+    #[arg(long)]
+    novelty_weight: Option<f64>,
+    /// how harmony is judged: product (of the terms of each interval) or
+    /// tenney (height, log2 of the product).
+    #[arg(long)]
+    harmony_metric: Option<HarmonyMetric>,
+    /// weight of how familiar the move from the last notes is, against 1
+    /// for harmony and novelty together.
+    #[arg(long)]
+    transition_weight: Option<f64>,
+    /// judge score past which the best notes found are a rest instead.
+    #[arg(long)]
+    tacet_score: Option<f64>,
+    /// how to search for the next notes: exhaustive, annealing or beam.
+    #[arg(long)]
+    search: Option<Search>,
+    /// how the notes tried are proposed, any of grid, lattice,
+    /// memory_products and random, e.g. grid,random.
+    #[arg(long, value_delimiter = ',')]
+    candidates: Option<Vec<Candidates>>,
+    /// largest term of a proposed note, or ratio_limit if that's higher.
+    #[arg(long)]
+    candidate_limit: Option<u64>,
+    /// most familiar ratios memory_products multiplies together.
+    #[arg(long, value_name = "RATIOS")]
+    memory_product_top: Option<usize>,
+    /// ratios random draws, simple ones more often.
+    #[arg(long, value_name = "RATIOS")]
+    random_candidates: Option<usize>,
+    /// search for the machine's next notes on another thread while the
+    /// current ones play. voices still search in line.
+    #[arg(long)]
+    search_ahead: bool,
+    /// seed for the random choices, so a run can be rendered again
+    /// exactly.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// draw the memory settings and judge weights, and a few ratios to
+    /// start out knowing, from the seed.
+    #[arg(long)]
+    personality: bool,
+    /// largest numerator or denominator of the ratios searched over.
+    #[arg(long)]
+    ratio_limit: Option<u64>,
+    /// largest prime allowed in the terms of the ratios searched over,
+    /// e.g. 5, 7 or 11.
+    #[arg(long)]
+    prime_limit: Option<u64>,
+    /// keep the notes to these ratios, in any octave, e.g.
+    /// 1/1,9/8,5/4,4/3,3/2,5/3,15/8.
+    #[arg(long, value_delimiter = ',')]
+    scale: Option<Vec<Frac>>,
+    /// lengths in steps the notesets are picked to last from, e.g. 4,2,1
+    /// for whole, half and quarter notes.
+    #[arg(long, value_delimiter = ',')]
+    durations: Option<Vec<Frac>>,
+    /// push tones closer than this many cents apart by octaves, up to
+    /// 1200.
+    #[arg(long, value_name = "CENTS")]
+    min_spacing_cents: Option<f64>,
+    /// octaves up or down each note may be moved to voice the chord
+    /// smoothly.
+    #[arg(long, value_name = "OCTAVES")]
+    register_octaves: Option<u32>,
+    /// log the tonal center and its drift to stderr every this many
+    /// chord changes.
+    #[arg(long, value_name = "CHANGES")]
+    center_report_changes: Option<u64>,
+    /// log the intervals of every chord to stderr, tab separated.
+    #[arg(long)]
+    log_intervals: bool,
+    /// share of real time rendering may take before harmonics are cut
+    /// back, e.g. 0.5 on a slow machine; 0 never cuts them.
+    #[arg(long, value_name = "SHARE")]
+    cpu_budget: Option<f64>,
+    /// chance that a note moving by a simple interval glides there over
+    /// the step instead of jumping, e.g. 0.3.
+    #[arg(long, value_name = "CHANCE")]
+    glide_chance: Option<f64>,
+    /// most complex interval that glides, as tenney height log2(a*b).
+    #[arg(long, value_name = "TENNEY")]
+    glide_max_tenney: Option<f64>,
+    /// widest interval that glides, in cents as voiced.
+    #[arg(long, value_name = "CENTS")]
+    glide_max_cents: Option<f64>,
+    /// chance each step that a held note is decorated with an ornament,
+    /// e.g. 0.2.
+    #[arg(long, value_name = "CHANCE")]
+    ornament_chance: Option<f64>,
+    /// the ornament: trill or turn.
+    #[arg(long)]
+    ornament: Option<OrnamentKind>,
+    /// ratio of the upper neighbour ornaments reach to, e.g. 9/8.
+    #[arg(long, value_name = "RATIO")]
+    ornament_neighbor: Option<Frac>,
+    /// ornament notes per second, slowed to fit a step.
+    #[arg(long, value_name = "HZ")]
+    ornament_rate_hz: Option<f64>,
+    /// level of a sub-oscillator an octave under the lowest tone,
+    /// relative to it.
+    #[arg(long, value_name = "LEVEL")]
+    sub_level: Option<f64>,
+    /// how the oscillators of a step play off one another: off, sync
+    /// (to the lowest tone) or cross_modulation (by the tone each makes
+    /// the simplest ratio with).
+    #[arg(long)]
+    interaction: Option<Interaction>,
+    /// peak phase deviation in radians under cross_modulation.
+    #[arg(long, value_name = "RADIANS")]
+    cross_mod_index: Option<f64>,
+    /// largest term of the ratios tones are paired by under
+    /// cross_modulation.
+    #[arg(long)]
+    pair_limit: Option<u64>,
+    /// level of breathy noise filtered around the tones, e.g. 0.3.
+    #[arg(long, value_name = "LEVEL")]
+    air_level: Option<f64>,
+    /// quality factor of the air's filters; higher hugs the tones closer.
+    #[arg(long, value_name = "Q")]
+    air_q: Option<f64>,
+    /// turn down tones masked by louder ones nearby.
+    #[arg(long)]
+    masking: bool,
+    /// move the base note to the most familiar ratio every this many
+    /// phrases.
+    #[arg(long, value_name = "PHRASES")]
+    modulation_phrases: Option<u64>,
+}
 
-fn simplify(Frac(a, b): Frac) -> Frac {
-    fn gcd(x: u64, y: u64) -> u64 {
-        if y == 0 {
-            x
-        } else {
-            gcd(y, x % y)
+/// the config file if one was given, with any flags laid over it.
+fn config(args: &Args) -> Result<Config, String> {
+    let mut config = match args.config {
+        Some(ref path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+            toml::from_str(&text).map_err(|e| format!("couldn't parse {}: {}", path.display(), e))?
         }
+        None => Config::default(),
+    };
+    config.pcm_hz = args.pcm_hz.unwrap_or(config.pcm_hz);
+    config.channels = args.channels.unwrap_or(config.channels);
+    config.outputs = args.outputs.clone().unwrap_or(config.outputs);
+    config.panning = args.panning.unwrap_or(config.panning);
+    config.pan_width = args.pan_width.unwrap_or(config.pan_width);
+    config.pan_octaves = args.pan_octaves.unwrap_or(config.pan_octaves);
+    config.passthrough = args.passthrough.or(config.passthrough);
+    config.steps_per_sec = args.steps_per_sec.unwrap_or(config.steps_per_sec);
+    config.steps_per_change = args.steps_per_change.unwrap_or(config.steps_per_change);
+    config.base_note = args.base_note.unwrap_or(config.base_note);
+    config.decay = args.decay.unwrap_or(config.decay);
+    config.familiarity_increment = args.familiarity_increment.unwrap_or(config.familiarity_increment);
+    config.max_familiarity = args.max_familiarity.or(config.max_familiarity);
+    config.min_familiarity = args.min_familiarity.unwrap_or(config.min_familiarity);
+    config.freeze_decay |= args.freeze_decay;
+    config.target_familiarity = args.target_familiarity.unwrap_or(config.target_familiarity);
+    config.harmony_weight = args.harmony_weight.unwrap_or(config.harmony_weight);
+    config.novelty_weight = args.novelty_weight.unwrap_or(config.novelty_weight);
+    config.harmony_metric = args.harmony_metric.unwrap_or(config.harmony_metric);
+    config.transition_weight = args.transition_weight.unwrap_or(config.transition_weight);
+    config.tacet_score = args.tacet_score.unwrap_or(config.tacet_score);
+    config.search = args.search.unwrap_or(config.search);
+    config.candidates = args.candidates.clone().unwrap_or(config.candidates);
+    config.candidate_limit = args.candidate_limit.unwrap_or(config.candidate_limit);
+    config.memory_product_top = args.memory_product_top.unwrap_or(config.memory_product_top);
+    config.random_candidates = args.random_candidates.unwrap_or(config.random_candidates);
+    config.search_ahead |= args.search_ahead;
+    config.seed = args.seed.unwrap_or(config.seed);
+    config.personality |= args.personality;
+    config.ratio_limit = args.ratio_limit.unwrap_or(config.ratio_limit);
+    config.prime_limit = args.prime_limit.or(config.prime_limit);
+    config.scale = args.scale.clone().unwrap_or(config.scale);
+    config.durations = args.durations.clone().unwrap_or(config.durations);
+    config.min_spacing_cents = args.min_spacing_cents.unwrap_or(config.min_spacing_cents);
+    config.register_octaves = args.register_octaves.unwrap_or(config.register_octaves);
+    config.center_report_changes = args.center_report_changes.unwrap_or(config.center_report_changes);
+    config.log_intervals |= args.log_intervals;
+    config.cpu_budget = args.cpu_budget.unwrap_or(config.cpu_budget);
+    config.air_level = args.air_level.unwrap_or(config.air_level);
+    config.air_q = args.air_q.unwrap_or(config.air_q);
+    config.glide_chance = args.glide_chance.unwrap_or(config.glide_chance);
+    config.glide_max_tenney = args.glide_max_tenney.unwrap_or(config.glide_max_tenney);
+    config.glide_max_cents = args.glide_max_cents.unwrap_or(config.glide_max_cents);
+    config.ornament_chance = args.ornament_chance.unwrap_or(config.ornament_chance);
+    config.ornament = args.ornament.unwrap_or(config.ornament);
+    config.ornament_neighbor = args.ornament_neighbor.clone().unwrap_or(config.ornament_neighbor);
+    config.ornament_rate_hz = args.ornament_rate_hz.unwrap_or(config.ornament_rate_hz);
+    config.sub_level = args.sub_level.unwrap_or(config.sub_level);
+    config.interaction = args.interaction.unwrap_or(config.interaction);
+    config.cross_mod_index = args.cross_mod_index.unwrap_or(config.cross_mod_index);
+    config.pair_limit = args.pair_limit.unwrap_or(config.pair_limit);
+    if args.masking && config.masking.is_none() {
+        config.masking = Some(Masking::default());
     }
+    config.modulation_phrases = args.modulation_phrases.unwrap_or(config.modulation_phrases);
 
-    let d = gcd(a, b);
-    Frac(a/d, b/d)
-}
+    if config.pcm_hz == 0 || config.steps_per_sec == 0 || config.steps_per_sec > config.pcm_hz {
+        return Err("pcm_hz and steps_per_sec must be positive, with at least one sample per step".to_owned());
+    }
+    if config.steps_per_change == 0 {
+        return Err("steps_per_change must be positive".to_owned());
+    }
+    if config.ratio_limit == 0 {
+        return Err("ratio_limit must be positive".to_owned());
+    }
+    if config.candidate_limit == 0 || config.candidate_limit > u32::MAX as u64 {
+        return Err(format!("candidate_limit must be from 1 to {}", u32::MAX));
+    }
+    if config.max_familiarity.is_some_and(|max| max.is_nan() || max <= 0_f64)
+        || config.min_familiarity.is_nan() || config.min_familiarity < 0_f64 {
+        return Err("max_familiarity must be positive and min_familiarity can't be negative".to_owned());
+    }
+    if config.prime_limit.is_some_and(|limit| limit < 2) {
+        return Err("prime_limit must be at least 2".to_owned());
+    }
+    if config.passthrough.is_some_and(|gain| gain.is_nan() || gain < 0_f64) {
+        return Err("passthrough gain can't be negative".to_owned());
+    }
+    let eq = &config.eq;
+    if !(eq.low_shelf_hz > 0_f64 && eq.low_shelf_hz < eq.high_shelf_hz && eq.high_shelf_hz.is_finite()) {
+        return Err("eq shelves must be above 0 Hz, the low one under the high".to_owned());
+    }
+    if [eq.low_gain_db, eq.mid_gain_db, eq.high_gain_db].iter().any(|gain| !gain.is_finite()) {
+        return Err("eq gains must be finite".to_owned());
+    }
+    if config.masking.as_ref().is_some_and(|masking| masking.max_cut_db.is_nan() || masking.max_cut_db < 0_f64) {
+        return Err("masking's max_cut_db can't be negative".to_owned());
+    }
+    if config.air_level.is_nan() || config.air_level < 0_f64 || !(config.air_q > 0_f64 && config.air_q.is_finite()) {
+        return Err("air_level can't be negative and air_q must be positive".to_owned());
+    }
+    let envelope = &config.envelope;
+    let times = [envelope.attack_ms, envelope.decay_ms, envelope.release_ms];
+    if times.iter().any(|ms| !ms.is_finite() || *ms < 0_f64) || !(0_f64..=1_f64).contains(&envelope.sustain) {
+        return Err("envelope times can't be negative and its sustain must be from 0 to 1".to_owned());
+    }
+    if !(0_f64..=1_f64).contains(&config.glide_chance) {
+        return Err("glide_chance must be from 0 to 1".to_owned());
+    }
+    let mut ornament_rates = config.voices.iter().map(|voice| voice.ornament_rate_hz).chain(Some(config.ornament_rate_hz));
+    if ornament_rates.any(|rate| rate.is_nan() || rate <= 0_f64) {
+        return Err("ornament_rate_hz must be positive".to_owned());
+    }
+    if config.sub_level.is_nan() || config.sub_level < 0_f64 {
+        return Err("sub_level can't be negative".to_owned());
+    }
+    if !config.cross_mod_index.is_finite() {
+        return Err("cross_mod_index must be finite".to_owned());
+    }
+    if config.pair_limit == 0 {
+        return Err("pair_limit must be positive".to_owned());
+    }
+    if config.cpu_budget.is_nan() || config.cpu_budget < 0_f64 {
+        return Err("cpu_budget can't be negative".to_owned());
+    }
+    if config.channels == 0 {
+        return Err("channels must be positive".to_owned());
+    }
+    let mut outputs = config.outputs.iter().chain(config.voices.iter().flat_map(|voice| &voice.outputs));
+    if outputs.any(|&output| output >= config.channels as usize) {
+        return Err(format!("outputs must be channels from 0 to {}", config.channels - 1));
+    }
+    if !(0_f64..=1_f64).contains(&config.pan_width) {
+        return Err("pan_width must be from 0 to 1".to_owned());
+    }
+    if !(config.pan_octaves > 0_f64 && config.pan_octaves.is_finite()) {
+        return Err("pan_octaves must be positive".to_owned());
+    }
+    if !(0_f64..1200_f64).contains(&config.min_spacing_cents) {
+        return Err("min_spacing_cents must be from 0 to under an octave, 1200".to_owned());
+    }
+    if config.register_octaves > 3 {
+        return Err("register_octaves can be at most 3".to_owned());
+    }
+    if args.duration.is_some_and(|secs| secs.is_nan() || secs < 0_f64) {
+        return Err("duration can't be negative".to_owned());
+    }
+    if args.steps == Some(0) {
+        return Err("steps must be positive".to_owned());
+    }
 
-/// judge a set of notes based on harmony.
-/// range: floats in [0, 1] and lower is better.
-fn judge_harmony(noteset: &[Frac], memory: &Memory) -> f64 {
-    let mut harmony_sum = 0_f64;
+    Ok(config)
+}
 
-    for &Frac(a1, b1) in noteset {
-        for (&Frac(a2, b2), &familiarity) in memory.iter() {
-            let Frac(a3, b3) = simplify(Frac(a1*b2, a2*b1));
-            harmony_sum += familiarity * (a3 as f64) * (b3 as f64);
+/// write frames to `out` a buffer at a time until the machine has
+/// finished or a write fails, calling `between_buffers` on the machine
+/// before each buffer.
+fn output_pcm<W, F>(machine: &mut HarmonyMachine, out: &mut W, mut between_buffers: F)
+    -> io::Result<()>
+    where W: Write, F: FnMut(&mut HarmonyMachine)
+{
+    let channels = machine.config().channels as usize;
+    let mut buffer = vec![0_i16; BUFFER_FRAMES * channels];
+    let mut bytes = vec![0_u8; buffer.len() * 2];
+    loop {
+        between_buffers(machine);
+        let left = machine.samples_left();
+        if left == Some(0) {
+            break;
         }
+        let frames = left.map_or(BUFFER_FRAMES, |left| BUFFER_FRAMES.min(left as usize));
+        let len = frames * channels;
+        machine.render_into(&mut buffer[..len]);
+        for (pair, &sample) in bytes.chunks_mut(2).zip(&buffer[..len]) {
+            Endianness::write_i16(pair, sample);
+        }
+        out.write_all(&bytes[..len * 2])?;
     }
-    let iterations = noteset.len()*memory.len();
-    let avg_harmony = (harmony_sum as f64)/(iterations as f64);
 
-    (1_f64 - 1_f64/(avg_harmony/5_f64).exp()).max(0_f64).min(1_f64)
+    out.flush()
 }
 
-/// judge a set of notes based on familiarity & novelty balance.
-/// range: floats in [0, 1] and lower is better.
-fn judge_novelty(noteset: &[Frac], memory: &Memory) -> f64 {
-    if noteset.len() < 1 {
-        panic!("judge_novelty: need at least 1 note");
-    }
+/// a writer that throws away what's written to it at the pace audio of
+/// `bytes_per_sec` would play, to run the machine in real time.
+struct Paced {
+    started: Instant,
+    bytes: u64,
+    bytes_per_sec: u64,
+}
 
-    let mut familiarity_sum = 0_f64;
-    for note in noteset {
-        let &familiarity = memory.get(note).unwrap_or(&0_f64);
-        familiarity_sum += familiarity;
-    }
+impl Write for Paced {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes += buf.len() as u64;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_sec as f64);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(wait);
+        }
 
-    let avg_familiarity = familiarity_sum / (noteset.len() as f64);
-    let target_familiarity = 0.1_f64;
-    let disparity = (target_familiarity - avg_familiarity).abs();
+        Ok(buf.len())
+    }
 
-    (1_f64 - 1_f64/disparity.exp()).max(0_f64).min(1_f64)
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
-/// judge a set of notes.
-/// range: floats in [0, 1] and lower is better.
-fn judge(noteset: &[Frac], memory: &Memory) -> f64 {
-    (judge_harmony(noteset, memory) + judge_novelty(noteset, memory))/2_f64
-}
+/// the time now in utc as YYYYMMDD-HHMMSS, for naming takes.
+fn timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // civil date from days since 1970-01-01, after Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
 
-fn forget(memory: &mut Memory) {
-    for (n, val) in memory.iter_mut() {
-        *val *= 0.75;
-    }
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
 }
 
-fn remember(note_set: &[Frac], memory: &mut Memory) {
-    let increase = 0.1_f64;
-    for note in note_set {
-        let val = match memory.get(note) {
-            Some(v) => v + increase,
-            None => increase,
+/// arm a take in `dir` named for the time, counting up from -2 if a take
+/// from the same second is already there. returns its path.
+fn arm_take(machine: &mut HarmonyMachine, dir: &Path) -> Result<PathBuf, String> {
+    let stamp = timestamp();
+    for count in 1.. {
+        let path = match count {
+            1 => dir.join(format!("take-{}.wav", stamp)),
+            _ => dir.join(format!("take-{}-{}.wav", stamp, count)),
         };
-        memory.insert(note.clone(), val);
+        match machine.arm(&path.to_string_lossy()) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("couldn't record to {}: {}", path.display(), e)),
+            Ok(()) => return Ok(path),
+        }
     }
+    unreachable!("ran out of take numbers")
 }
 
-/// step to a set of notes that minimizes the judge function.
-fn step_notes(note_set: &[Frac], memory: &Memory) -> Vec<Frac> {
-    let mut best: Vec<Frac> = note_set.to_owned();
-    let mut best_score = 1_f64;
-    for i in 0..note_set.len() {
-        for a in 1..12 {
-            for b in 1..12 {
-                let possibility = simplify(Frac(a, b));
-                if (note_set.contains(&possibility)) {
-                    continue;
-                }
-                let note_set2: Vec<Frac> = note_set[0..i].iter()
-                                                         .chain(note_set[i+1..note_set.len()].iter())
-                                                         .chain([possibility].iter())
-                                                         .map(|n| n.clone())
-                                                         .collect();
-                let score = judge(&note_set2, memory);
-                if score < best_score {
-                    best = note_set2;
-                    best_score = score;
-                }
+/// lines of stdin as they arrive, for commands while the machine plays.
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
             }
         }
-    }
-
-    best
-}
-
-type PCM_Sample = i16;
-static PCM_HZ: u64 = 44100_u64;
-static STEPS_PER_SEC: u64 = 4;
-static BASE_NOTE: f64 = 250_f64;
-type Endianness = LittleEndian;
+    });
 
-fn sine_wave(freq: f64, step: u64) -> f64 {
-    (2.0*std::f64::consts::PI*(step as f64)*freq/(PCM_HZ as f64)).sin()
+    receiver
 }
 
-fn sine_waves(base_note: f64, fractions: &[Frac], step: u64) -> f64 {
-    let mut sum = 0_f64;
-    for &Frac(a, b) in fractions {
-        let freq = (base_note / (b as f64)) * (a as f64);
-        sum += sine_wave(freq, step);
+/// carry out any commands that have come in since last time.
+fn run_commands(machine: &mut HarmonyMachine, commands: &Receiver<String>) {
+    while let Ok(line) = commands.try_recv() {
+        if let Err(e) = run_command(machine, &line) {
+            eprintln!("{}", e);
+        }
     }
-
-    sum / (fractions.len() as f64)
 }
 
-fn linear_envelope(sample: f64, duration: u64, progress: u64) -> f64 {
-    sample * (progress as f64) / (duration as f64)
+fn run_command(machine: &mut HarmonyMachine, line: &str) -> Result<(), String> {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("profile") => machine.switch_profile(words.next().ok_or("profile needs a name")?),
+        Some("blend") => {
+            let mut weights = vec![];
+            for word in words {
+                let (name, weight) = word.split_once('=').ok_or_else(|| format!("expected NAME=WEIGHT, got {}", word))?;
+                let weight: f64 = weight.parse().map_err(|_| format!("bad weight {}", weight))?;
+                weights.push((name, weight));
+            }
+            machine.blend_profiles(&weights)
+        }
+        Some("morph") => {
+            let usage = || "expected morph FROM TO CHANGES".to_owned();
+            let (from, to) = (words.next().ok_or_else(usage)?, words.next().ok_or_else(usage)?);
+            let changes = words.next().and_then(|changes| changes.parse().ok()).ok_or_else(usage)?;
+            machine.morph_profiles(from, to, changes)
+        }
+        Some("avert") => {
+            let notes: Vec<Frac> = match words.next() {
+                Some(_) => line.split_whitespace().skip(1).map(str::parse).collect::<Result<_, _>>()?,
+                None => machine.notes().to_vec(),
+            };
+            for note in &notes {
+                machine.avert(note);
+            }
+            Ok(())
+        }
+        Some("rollback") => {
+            let changes = match words.next() {
+                Some(changes) => changes.parse().map_err(|_| format!("bad number of chord changes {}", changes))?,
+                None => 1,
+            };
+            let rolled = machine.rollback(changes);
+            if rolled < changes {
+                eprintln!("only {} chord changes kept, rolled back to the oldest", rolled);
+            }
+            Ok(())
+        }
+        Some("arm") => {
+            let path = arm_take(machine, Path::new(words.next().unwrap_or(".")))?;
+            eprintln!("recording to {}", path.display());
+            Ok(())
+        }
+        Some("disarm") => {
+            if let Some(path) = machine.disarm().map_err(|e| format!("couldn't finish the take: {}", e))? {
+                eprintln!("recorded {}", path);
+            }
+            Ok(())
+        }
+        Some("pause") => {
+            machine.pause(true);
+            Ok(())
+        }
+        Some("play") => {
+            machine.pause(false);
+            Ok(())
+        }
+        Some(other) => Err(format!("unknown command {}, expected profile, blend, morph, avert, rollback, arm, disarm, pause or play", other)),
+        None => Ok(()),
+    }
 }
 
-fn output_pcm() {
-    let mut notes = vec![Frac(1, 2), Frac(1, 1), Frac(1, 3), Frac(1, 5), Frac(1, 7)];
-    let mut memory = Memory::new();
-
-    let mut j=0;
-    for i in (0_u64..u64::max_value()).cycle() {
-        let sample = sine_waves(BASE_NOTE, &notes, i) *
-                     (PCM_Sample::max_value() as f64);
-
-        let enveloped = linear_envelope(sample, j, PCM_HZ/STEPS_PER_SEC);
-
-        let bounded = enveloped.min(PCM_Sample::max_value() as f64 - 1_f64)
-                               .max(PCM_Sample::min_value() as f64 + 1_f64);
-
-        let as_sample: PCM_Sample = bounded as PCM_Sample;
-        std::io::stdout().write_i16::<Endianness>(as_sample).unwrap();
+fn main() {
+    let args = Args::parse();
+    let config = config(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let (pcm_hz, channels) = (config.pcm_hz, config.channels);
+    let mut machine = HarmonyMachine::new(config);
+    if machine.config().personality {
+        let drawn = machine.config();
+        eprintln!("personality {}: decay {:.2}, familiarity_increment {:.2}, target_familiarity {:.2}, \
+                   harmony_weight {:.2}, novelty_weight {:.2}, transition_weight {:.2}",
+                  drawn.seed, drawn.decay, drawn.familiarity_increment, drawn.target_familiarity, drawn.harmony_weight,
+                  drawn.novelty_weight, drawn.transition_weight);
+    }
+    if let Some(ref path) = args.import {
+        if let Err(e) = machine.import_memory(&path.to_string_lossy()) {
+            eprintln!("couldn't import {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+    if let Some(ref path) = args.resume {
+        if path.exists() {
+            if let Err(e) = machine.resume_state(&path.to_string_lossy()) {
+                eprintln!("couldn't resume from {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+    for profile in &args.profile {
+        let loaded = profile.split_once('=')
+                            .ok_or_else(|| "expected NAME=FILE.json".to_owned())
+                            .and_then(|(name, path)| machine.load_profile(name, path).map_err(|e| format!("{}: {}", path, e)));
+        if let Err(e) = loaded {
+            eprintln!("couldn't load profile {}: {}", profile, e);
+            process::exit(1);
+        }
+    }
+    if let Some((name, _)) = args.profile.first().and_then(|profile| profile.split_once('=')) {
+        machine.switch_profile(name).expect("profile was just loaded");
+    }
+    if let Some(steps) = args.steps {
+        machine.finish_after(steps - 1);
+    }
+    if let Some(secs) = args.duration {
+        machine.finish_at((secs * pcm_hz as f64).round() as u64);
+    }
+    let commands = read_commands();
+    // the first ctrl-c finishes the step and fades out so the state and
+    // files are written; a second gives up at once
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler = {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || {
+            if interrupted.swap(true, Ordering::SeqCst) {
+                process::exit(130);
+            }
+        })
+    };
+    if let Err(e) = handler {
+        eprintln!("couldn't catch ctrl-c: {}", e);
+    }
+    let between_buffers = move |machine: &mut HarmonyMachine| {
+        run_commands(machine, &commands);
+        if interrupted.load(Ordering::SeqCst) {
+            machine.finish_after(0);
+        }
+    };
+    if args.midi.is_some() {
+        machine.record_midi();
+    }
+    if args.heatmap.is_some() {
+        machine.record_heatmap();
+    }
+    if let Some(ref target) = args.osc {
+        if let Err(e) = machine.send_osc(target) {
+            eprintln!("couldn't send osc to {}: {}", target, e);
+            process::exit(1);
+        }
+    }
 
-        j += 1;
-        if j == PCM_HZ/STEPS_PER_SEC {
-            j = 0;
-            forget(&mut memory);
-            notes = step_notes(&notes, &memory);
-            remember(&notes, &mut memory);
+    if args.play {
+        #[cfg(feature = "playback")]
+        {
+            machine = harmonymachine::playback::play(machine, between_buffers).unwrap_or_else(|e| {
+                eprintln!("couldn't play: {}", e);
+                process::exit(1);
+            });
+        }
+        #[cfg(not(feature = "playback"))]
+        {
+            eprintln!("--play needs a build with the playback feature");
+            process::exit(2);
         }
+    } else if let Some(ref path) = args.out {
+        let result = File::create(path)
+            .and_then(|file| WavWriter::new(BufWriter::new(file), pcm_hz as u32, channels))
+            .and_then(|mut wav| {
+                output_pcm(&mut machine, &mut wav, between_buffers)?;
+                wav.finish()
+            });
+        if let Err(e) = result {
+            eprintln!("couldn't write {}: {}", path.display(), e);
+        }
+    } else if args.no_audio {
+        let mut paced = Paced { started: Instant::now(), bytes: 0, bytes_per_sec: pcm_hz * channels as u64 * 2 };
+        let _ = output_pcm(&mut machine, &mut paced, between_buffers);
+    } else {
+        let _ = output_pcm(&mut machine, &mut io::stdout(), between_buffers);
     }
-}
 
-fn main() {
-    output_pcm();
+    match machine.disarm() {
+        Ok(Some(path)) => eprintln!("recorded {}", path),
+        Ok(None) => {}
+        Err(e) => eprintln!("couldn't finish the take: {}", e),
+    }
+    if let Some(ref path) = args.resume {
+        if let Err(e) = machine.save_state(&path.to_string_lossy()) {
+            eprintln!("couldn't save state to {}: {}", path.display(), e);
+        }
+    }
+    if let Some(ref path) = args.midi {
+        if let Err(e) = machine.write_midi(&path.to_string_lossy()) {
+            eprintln!("couldn't write {}: {}", path.display(), e);
+        }
+    }
+    if PRINT_SUMMARY {
+        machine.print_summary();
+    }
+    if let Some(ref path) = args.heatmap {
+        if let Err(e) = machine.write_heatmap(&path.to_string_lossy()) {
+            eprintln!("couldn't write {}: {}", path.display(), e);
+        }
+    }
 }
@@ -0,0 +1,20 @@
+//! transcendental functions. with the deterministic feature they come
+//! from libm so results don't depend on the platform's math library.
+
+#[cfg(feature = "deterministic")]
+pub use libm::{atan, cos, exp, log2, pow, sin, tanh};
+
+#[cfg(not(feature = "deterministic"))]
+pub fn atan(x: f64) -> f64 { x.atan() }
+#[cfg(not(feature = "deterministic"))]
+pub fn cos(x: f64) -> f64 { x.cos() }
+#[cfg(not(feature = "deterministic"))]
+pub fn exp(x: f64) -> f64 { x.exp() }
+#[cfg(not(feature = "deterministic"))]
+pub fn log2(x: f64) -> f64 { x.log2() }
+#[cfg(not(feature = "deterministic"))]
+pub fn pow(x: f64, y: f64) -> f64 { x.powf(y) }
+#[cfg(not(feature = "deterministic"))]
+pub fn sin(x: f64) -> f64 { x.sin() }
+#[cfg(not(feature = "deterministic"))]
+pub fn tanh(x: f64) -> f64 { x.tanh() }
@@ -0,0 +1,261 @@
+//! standard midi file of the chosen notes, one channel per note so each
+//! can be pitch bent to its just ratio.
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use analysis::cents;
+use math;
+
+/// pitch bend range set on every channel, in semitones each way.
+pub static BEND_SEMITONES: u8 = 2;
+static TICKS_PER_STEP: u16 = 480;
+/// channels notes are spread over, leaving out 10 for drums.
+static CHANNELS: &[u8] = &[0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15];
+
+/// the notes of each step as midi events, one step to a quarter note.
+pub struct MidiRecorder {
+    track: Vec<u8>,
+    first_sample: u64,
+    step_samples: u64,
+    last_tick: u64,
+    held: Vec<Option<(u8, u16)>>,
+}
+
+impl MidiRecorder {
+    /// a recorder starting at sample `first_sample`, with steps of
+    /// `step_samples`.
+    pub fn new(first_sample: u64, step_samples: u64, steps_per_sec: u64) -> MidiRecorder {
+        let mut recorder = MidiRecorder { track: vec![], first_sample, step_samples, last_tick: 0, held: vec![] };
+        let quarter_micros = 1_000_000 / steps_per_sec as u32;
+        recorder.event(0, &[0xFF, 0x51, 0x03,
+                            (quarter_micros >> 16) as u8, (quarter_micros >> 8) as u8, quarter_micros as u8]);
+        for &channel in CHANNELS {
+            for &(controller, value) in &[(101, 0), (100, 0), (6, BEND_SEMITONES), (38, 0)] {
+                recorder.event(0, &[0xB0 | channel, controller, value]);
+            }
+        }
+
+        recorder
+    }
+
+    /// the frequencies sounding from `sample` on, none for a rest. notes
+    /// that carry on from the last step are held rather than restruck.
+    pub fn record(&mut self, sample: u64, freqs: &[f64], velocity: u8) {
+        let tick = sample.wrapping_sub(self.first_sample) * TICKS_PER_STEP as u64 / self.step_samples;
+        let next: Vec<Option<(u8, u16)>> = freqs.iter().take(CHANNELS.len()).map(|&f| Some(key_and_bend(f))).collect();
+
+        for (slot, &channel) in CHANNELS.iter().enumerate().take(self.held.len().max(next.len())) {
+            let held = self.held.get(slot).cloned().unwrap_or(None);
+            let note = next.get(slot).cloned().unwrap_or(None);
+            if held == note {
+                continue;
+            }
+            if let Some((key, _)) = held {
+                self.event(tick, &[0x80 | channel, key, 0]);
+            }
+            if let Some((key, bend)) = note {
+                self.event(tick, &[0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+                self.event(tick, &[0x90 | channel, key, velocity.clamp(1, 127)]);
+            }
+        }
+        self.held = next;
+    }
+
+    /// write a format 0 midi file, releasing held notes a step after the
+    /// last one recorded.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        self.write_to(io::BufWriter::new(File::create(path)?))
+    }
+
+    /// write the midi file to `out`.
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut ending = MidiRecorder { track: vec![], first_sample: 0, step_samples: 1, last_tick: self.last_tick, held: vec![] };
+        let end = self.last_tick + TICKS_PER_STEP as u64;
+        for (slot, held) in self.held.iter().enumerate() {
+            if let Some((key, _)) = *held {
+                ending.event(end, &[0x80 | CHANNELS[slot], key, 0]);
+            }
+        }
+        ending.event(end, &[0xFF, 0x2F, 0x00]);
+
+        out.write_all(b"MThd")?;
+        out.write_u32::<BigEndian>(6)?;
+        out.write_u16::<BigEndian>(0)?;
+        out.write_u16::<BigEndian>(1)?;
+        out.write_u16::<BigEndian>(TICKS_PER_STEP)?;
+        out.write_all(b"MTrk")?;
+        out.write_u32::<BigEndian>((self.track.len() + ending.track.len()) as u32)?;
+        out.write_all(&self.track)?;
+        out.write_all(&ending.track)?;
+
+        out.flush()
+    }
+
+    fn event(&mut self, tick: u64, bytes: &[u8]) {
+        let mut delta = tick - self.last_tick;
+        let mut quantity = vec![(delta & 0x7F) as u8];
+        delta >>= 7;
+        while delta > 0 {
+            quantity.push(0x80 | (delta & 0x7F) as u8);
+            delta >>= 7;
+        }
+        self.track.extend(quantity.iter().rev());
+        self.track.extend_from_slice(bytes);
+        self.last_tick = tick;
+    }
+}
+
+/// frequencies of every note struck in a standard midi file, each bent
+/// by its channel's pitch bend at BEND_SEMITONES.
+pub fn read_notes(bytes: &[u8]) -> io::Result<Vec<f64>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad midi file: {}", what));
+    if !bytes.starts_with(b"MThd") {
+        return Err(invalid("no header"));
+    }
+
+    let mut freqs = vec![];
+    let mut at = 0;
+    while at + 8 <= bytes.len() {
+        let len = u32::from_be_bytes([bytes[at + 4], bytes[at + 5], bytes[at + 6], bytes[at + 7]]) as usize;
+        let chunk = bytes.get(at + 8..at + 8 + len).ok_or_else(|| invalid("chunk runs past the end"))?;
+        if &bytes[at..at + 4] == b"MTrk" {
+            read_track(chunk, &mut freqs).ok_or_else(|| invalid("track runs past the end"))?;
+        }
+        at += 8 + len;
+    }
+
+    Ok(freqs)
+}
+
+/// add the frequencies of the notes struck in a track to `freqs`, or
+/// none if it ends early.
+fn read_track(track: &[u8], freqs: &mut Vec<f64>) -> Option<()> {
+    let mut bends = [8192_u16; 16];
+    let (mut at, mut status) = (0, 0_u8);
+    while at < track.len() {
+        quantity(track, &mut at)?;
+        if *track.get(at)? >= 0x80 {
+            status = track[at];
+            at += 1;
+        }
+        match status {
+            0xFF => {
+                at += 1;
+                skip_data(track, &mut at)?;
+            }
+            0xF0 | 0xF7 => skip_data(track, &mut at)?,
+            _ => {
+                let data = track.get(at..at + if status & 0xE0 == 0xC0 { 1 } else { 2 })?;
+                let channel = (status & 0x0F) as usize;
+                match status & 0xF0 {
+                    0x90 if data[1] > 0 => {
+                        let bend = (bends[channel] as f64 - 8192_f64) / 8192_f64 * BEND_SEMITONES as f64;
+                        freqs.push(440_f64 * math::pow(2_f64, (data[0] as f64 + bend - 69_f64) / 12_f64));
+                    }
+                    0xE0 => bends[channel] = data[0] as u16 | (data[1] as u16) << 7,
+                    _ => {}
+                }
+                at += data.len();
+            }
+        }
+    }
+
+    Some(())
+}
+
+/// move past the data of a meta or sysex event at `at`, after its
+/// length, or none if the length runs past the end.
+fn skip_data(track: &[u8], at: &mut usize) -> Option<()> {
+    let len = quantity(track, at)? as usize;
+    *at = at.checked_add(len).filter(|&end| end <= track.len())?;
+    Some(())
+}
+
+/// read a variable length quantity at `at`, moving past it.
+fn quantity(bytes: &[u8], at: &mut usize) -> Option<u64> {
+    let mut value = 0_u64;
+    loop {
+        let byte = *bytes.get(*at)?;
+        *at += 1;
+        value = (value << 7) | (byte & 0x7F) as u64;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+}
+
+/// nearest midi key to a frequency, and the 14 bit pitch bend that
+/// makes up the difference.
+fn key_and_bend(freq: f64) -> (u8, u16) {
+    let pitch = 69_f64 + cents(freq / 440_f64) / 100_f64;
+    let key = pitch.round().clamp(0_f64, 127_f64);
+    let bend = 8192_f64 + (pitch - key) / (BEND_SEMITONES as f64) * 8192_f64;
+
+    (key as u8, bend.round().clamp(0_f64, 16383_f64) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {nearest_ratio, Frac};
+
+    #[test]
+    fn round_trip_keeps_ratios() {
+        let base = 261.63_f64;
+        let steps: Vec<Vec<Frac>> = vec![vec![Frac(1, 1), Frac(5, 4), Frac(3, 2)],
+                                         vec![Frac(1, 1), Frac(7, 4), Frac(11, 8)],
+                                         vec![],
+                                         vec![Frac(9, 8), Frac(13, 7)]];
+        let mut recorder = MidiRecorder::new(0, 100, 4);
+        for (step, notes) in steps.iter().enumerate() {
+            let freqs: Vec<f64> = notes.iter().map(|&Frac(a, b)| base * a as f64 / b as f64).collect();
+            recorder.record(step as u64 * 100, &freqs, 100);
+        }
+        let mut bytes = vec![];
+        recorder.write_to(&mut bytes).unwrap();
+
+        let freqs = read_notes(&bytes).unwrap();
+        let struck: Vec<Frac> = vec![Frac(1, 1), Frac(5, 4), Frac(3, 2), Frac(7, 4), Frac(11, 8), Frac(9, 8), Frac(13, 7)];
+        assert_eq!(freqs.len(), struck.len());
+        for (freq, note) in freqs.iter().zip(&struck) {
+            let exact = base * note.0 as f64 / note.1 as f64;
+            // a 14 bit bend over BEND_SEMITONES each way is finer than 0.05 cents
+            assert!(cents(freq / exact).abs() < 0.05, "{} against {}", freq, exact);
+            assert_eq!(nearest_ratio(freq / base, 16), *note);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(read_notes(b"").is_err());
+        assert!(read_notes(b"RIFF\0\0\0\x06\0\0\0\x01\x01\xE0").is_err());
+
+        let mut bytes = vec![];
+        MidiRecorder::new(0, 100, 4).write_to(&mut bytes).unwrap();
+        let truncated = &bytes[..bytes.len() - 2];
+        assert!(read_notes(truncated).is_err());
+
+        // a track whose note on is missing its velocity
+        let mut track = b"MThd\0\0\0\x06\0\0\0\x01\x01\xE0MTrk\0\0\0\x03".to_vec();
+        track.extend(&[0x00, 0x90, 0x40]);
+        assert!(read_notes(&track).is_err());
+    }
+
+    #[test]
+    fn rejects_event_lengths_past_the_track() {
+        let header = b"MThd\0\0\0\x06\0\0\0\x01\x01\xE0MTrk\0\0\0";
+        // a sysex claiming more data than the track holds
+        let mut sysex = header.to_vec();
+        sysex.extend(&[0x05, 0x00, 0xF0, 0x10, 0x01, 0x02]);
+        assert!(read_notes(&sysex).is_err());
+        // a meta event whose length would overflow the position
+        let mut meta = header.to_vec();
+        meta.extend(&[0x0D, 0x00, 0xFF, 0x01]);
+        meta.extend(&[0xFF; 9]);
+        meta.push(0x7F);
+        assert!(read_notes(&meta).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+
+use {fold, Frac, Memory};
+
+/// a run of MOTIF_LEN chord changes averaging a judge score under
+/// MOTIF_SCORE is kept as a motif, and every MOTIF_RECALL_CHANGES changes
+/// one of the last MAX_MOTIFS kept is played again.
+pub static MOTIF_LEN: usize = 4;
+pub static MOTIF_SCORE: f64 = 0.095_f64;
+pub static MOTIF_RECALL_CHANGES: u64 = 64;
+pub static MAX_MOTIFS: usize = 8;
+
+/// runs of well-judged notesets, kept so they can come back later.
+pub struct Motifs {
+    recent: VecDeque<(Vec<Frac>, f64)>,
+    stored: VecDeque<Vec<Vec<Frac>>>,
+    playing: VecDeque<Vec<Frac>>,
+    changes: u64,
+}
+
+impl Motifs {
+    pub fn new() -> Motifs {
+        Motifs {
+            recent: VecDeque::new(),
+            stored: VecDeque::new(),
+            playing: VecDeque::new(),
+            changes: 0,
+        }
+    }
+
+    /// note a chosen noteset and its score, keeping the last MOTIF_LEN
+    /// as a motif when they judged well on average.
+    pub fn record(&mut self, noteset: &[Frac], score: f64) {
+        self.recent.push_back((noteset.to_owned(), score));
+        if self.recent.len() > MOTIF_LEN {
+            self.recent.pop_front();
+        }
+
+        let avg_score = self.recent.iter().map(|&(_, s)| s).sum::<f64>() / (self.recent.len() as f64);
+        if self.recent.len() == MOTIF_LEN && avg_score < MOTIF_SCORE {
+            self.stored.push_back(self.recent.drain(..).map(|(n, _)| n).collect());
+            if self.stored.len() > MAX_MOTIFS {
+                self.stored.pop_front();
+            }
+        }
+    }
+
+    /// the next noteset of a recalled motif, if one is playing or due.
+    /// recalled motifs are transposed by the most familiar ratio when that
+    /// keeps them inside the search grid of terms up to `limit`.
+    pub fn next(&mut self, memory: &Memory, limit: u64) -> Option<Vec<Frac>> {
+        self.changes += 1;
+        if self.playing.is_empty() && self.changes.is_multiple_of(MOTIF_RECALL_CHANGES) && !self.stored.is_empty() {
+            let motif = &self.stored[(self.changes / MOTIF_RECALL_CHANGES) as usize % self.stored.len()];
+            let by = memory.iter()
+                           .max_by(|x, y| x.1.partial_cmp(y.1).unwrap())
+                           .map(|(note, _)| note.clone())
+                           .unwrap_or(Frac(1, 1));
+            let transposed: Option<VecDeque<Vec<Frac>>> = motif.iter().map(|noteset| {
+                noteset.iter().map(|note| note.checked_mul(&by).map(fold)).collect()
+            }).collect();
+            self.playing = match transposed {
+                Some(transposed) if transposed.iter().flatten().all(|&Frac(a, b)| a <= limit && b <= limit) => transposed,
+                _ => motif.iter().cloned().collect(),
+            };
+        }
+
+        self.playing.pop_front()
+    }
+}
+
+impl Default for Motifs {
+    fn default() -> Motifs {
+        Motifs::new()
+    }
+}
@@ -0,0 +1,153 @@
+//! open sound control messages of the chosen notes over udp, so another
+//! program can do the rendering.
+
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use Frac;
+
+/// what every address starts with.
+pub static ADDRESS_PREFIX: &str = "/harmonymachine";
+
+/// an osc argument.
+enum Arg {
+    Int(i32),
+    Float(f32),
+}
+
+/// sends a bundle each step to one host and port.
+pub struct OscSender {
+    socket: UdpSocket,
+}
+
+impl OscSender {
+    /// a sender to `target`, e.g. "127.0.0.1:57120".
+    pub fn new<A: ToSocketAddrs>(target: A) -> io::Result<OscSender> {
+        let addr = target.to_socket_addrs()?
+                         .next()
+                         .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.connect(addr)?;
+
+        Ok(OscSender { socket })
+    }
+
+    /// send a step as a bundle of three messages, empty but for the
+    /// step number during a rest:
+    ///
+    /// - /harmonymachine/freqs: the step, then each note's frequency
+    /// - /harmonymachine/ratios: the step, then each note's a and b
+    /// - /harmonymachine/scores: the step, then harmony, novelty and
+    ///   tension
+    pub fn send(&self, step: u64, freqs: &[f64], notes: &[Frac], scores: &[f64]) -> io::Result<()> {
+        self.socket.send(&bundle(step, freqs, notes, scores)?)?;
+
+        Ok(())
+    }
+}
+
+/// the bundle `OscSender::send` sends.
+fn bundle(step: u64, freqs: &[f64], notes: &[Frac], scores: &[f64]) -> io::Result<Vec<u8>> {
+    let step = Arg::Int(step as i32);
+    let freqs: Vec<Arg> = freqs.iter().map(|&freq| Arg::Float(freq as f32)).collect();
+    let ratios: Vec<Arg> = notes.iter().flat_map(|&Frac(a, b)| vec![Arg::Int(a as i32), Arg::Int(b as i32)]).collect();
+    let scores: Vec<Arg> = scores.iter().map(|&score| Arg::Float(score as f32)).collect();
+
+    let mut bundle = b"#bundle\0".to_vec();
+    // 1 is osc's "immediately"
+    bundle.write_u64::<BigEndian>(1)?;
+    for (name, args) in &[("freqs", freqs), ("ratios", ratios), ("scores", scores)] {
+        let message = message(&format!("{}/{}", ADDRESS_PREFIX, name), &step, args)?;
+        bundle.write_i32::<BigEndian>(message.len() as i32)?;
+        bundle.extend(message);
+    }
+
+    Ok(bundle)
+}
+
+/// an osc message to `address` with `first` and then `args`.
+fn message(address: &str, first: &Arg, args: &[Arg]) -> io::Result<Vec<u8>> {
+    let all: Vec<&Arg> = Some(first).into_iter().chain(args).collect();
+    let tags: String = Some(',').into_iter()
+                                .chain(all.iter().map(|arg| match **arg {
+                                    Arg::Int(_) => 'i',
+                                    Arg::Float(_) => 'f',
+                                }))
+                                .collect();
+
+    let mut message = vec![];
+    padded(&mut message, address);
+    padded(&mut message, &tags);
+    for arg in all {
+        match *arg {
+            Arg::Int(i) => message.write_i32::<BigEndian>(i)?,
+            Arg::Float(f) => message.write_f32::<BigEndian>(f)?,
+        }
+    }
+
+    Ok(message)
+}
+
+/// an osc string: null terminated and padded to a multiple of 4 bytes.
+fn padded(out: &mut Vec<u8>, s: &str) {
+    out.extend(s.as_bytes());
+    out.extend(vec![0_u8; 4 - s.len() % 4]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_null_terminated_and_padded() {
+        let pad = |s: &str| {
+            let mut out = vec![];
+            padded(&mut out, s);
+            out
+        };
+        assert_eq!(pad(""), b"\0\0\0\0");
+        assert_eq!(pad("/a"), b"/a\0\0");
+        assert_eq!(pad("/ab"), b"/ab\0");
+        assert_eq!(pad("/abc"), b"/abc\0\0\0\0");
+    }
+
+    #[test]
+    fn message_bytes() {
+        let bytes = message("/hm/x", &Arg::Int(7), &[Arg::Float(0.5), Arg::Int(-1)]).unwrap();
+        let mut expected = b"/hm/x\0\0\0,ifi\0\0\0\0".to_vec();
+        expected.extend(&[0, 0, 0, 7]);
+        expected.extend(&[0x3F, 0, 0, 0]);
+        expected.extend(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn bundle_bytes() {
+        let bytes = bundle(3, &[440_f64], &[Frac(3, 2)], &[0.25_f64]).unwrap();
+
+        let mut freqs = b"/harmonymachine/freqs\0\0\0,if\0".to_vec();
+        freqs.extend(&[0, 0, 0, 3, 0x43, 0xDC, 0, 0]);
+        let mut ratios = b"/harmonymachine/ratios\0\0,iii\0\0\0\0".to_vec();
+        ratios.extend(&[0, 0, 0, 3, 0, 0, 0, 3, 0, 0, 0, 2]);
+        let mut scores = b"/harmonymachine/scores\0\0,if\0".to_vec();
+        scores.extend(&[0, 0, 0, 3, 0x3E, 0x80, 0, 0]);
+        let mut expected = b"#bundle\0".to_vec();
+        expected.extend(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for message in &[freqs, ratios, scores] {
+            assert_eq!(message.len() % 4, 0);
+            expected.extend(&[0, 0, 0, message.len() as u8]);
+            expected.extend(message);
+        }
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn rest_bundle_has_only_the_step() {
+        let bytes = bundle(9, &[], &[], &[]).unwrap();
+        let freqs = &bytes[16 + 4..];
+        assert_eq!(&freqs[..28], b"/harmonymachine/freqs\0\0\0,i\0\0");
+        assert_eq!(&freqs[28..32], &[0, 0, 0, 9]);
+    }
+}
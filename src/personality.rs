@@ -0,0 +1,51 @@
+//! a random but coherent character for the machine: how fast it learns
+//! and forgets, what its judge weighs, and a few ratios it starts out
+//! knowing, drawn within bounds that still make music.
+
+use std::ops::Range;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use {in_scale, simplify, within_prime_limit, Config, Frac, Memory};
+
+/// the bounds each part of a personality is drawn from.
+pub static DECAY: Range<f64> = 0.6_f64..0.85_f64;
+pub static TARGET_FAMILIARITY: Range<f64> = 0.04_f64..0.1_f64;
+pub static HARMONY_WEIGHT: Range<f64> = 0.5_f64..2_f64;
+pub static NOVELTY_WEIGHT: Range<f64> = 0.5_f64..2_f64;
+pub static TRANSITION_WEIGHT: Range<f64> = 0_f64..0.5_f64;
+/// how many times the target familiarity a note played every chord
+/// change settles at, which the familiarity increment is drawn to give.
+/// much past the default's 4 and every note grows too familiar to play.
+pub static SATURATION: Range<f64> = 2_f64..4_f64;
+/// how many ratios a personality starts out knowing, how familiar, and
+/// the largest term they're drawn with.
+pub static FAVOURITES: usize = 4;
+pub static FAVOURITE_FAMILIARITY: Range<f64> = 0.1_f64..1_f64;
+pub static FAVOURITE_LIMIT: u64 = 8;
+
+/// `config` with a personality drawn from `rng` laid over it, and the
+/// memory it starts with.
+pub fn personality(config: &Config, rng: &mut StdRng) -> (Config, Memory) {
+    let mut config = config.clone();
+    config.decay = rng.gen_range(DECAY.clone());
+    config.target_familiarity = rng.gen_range(TARGET_FAMILIARITY.clone());
+    config.familiarity_increment = config.target_familiarity * (1_f64 - config.decay) * rng.gen_range(SATURATION.clone());
+    config.harmony_weight = rng.gen_range(HARMONY_WEIGHT.clone());
+    config.novelty_weight = rng.gen_range(NOVELTY_WEIGHT.clone());
+    config.transition_weight = rng.gen_range(TRANSITION_WEIGHT.clone());
+
+    let limit = FAVOURITE_LIMIT.min(config.ratio_limit);
+    let mut ratios: Vec<Frac> = (1..=limit).flat_map(|a| (1..=limit).map(move |b| simplify(Frac(a, b))))
+                                           .filter(|note| config.prime_limit.is_none_or(|primes| within_prime_limit(note, primes)))
+                                           .filter(|note| in_scale(note, &config.scale))
+                                           .collect();
+    ratios.sort();
+    ratios.dedup();
+    let favourites: Vec<Frac> = ratios.choose_multiple(rng, FAVOURITES).cloned().collect();
+    let memory = favourites.into_iter().map(|note| (note, rng.gen_range(FAVOURITE_FAMILIARITY.clone()))).collect();
+
+    (config, memory)
+}
@@ -0,0 +1,270 @@
+//! real-time playback through the default output device.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig};
+
+use HarmonyMachine;
+
+/// frames rendered ahead of the device. more survives longer stalls in
+/// the generator, less reacts sooner.
+pub static RING_FRAMES: usize = 8192;
+static CHUNK_FRAMES: usize = 512;
+/// input frames held for passthrough before the oldest are dropped, to
+/// keep the player hearing themselves without delay.
+pub static PASSTHROUGH_FRAMES: usize = 1024;
+
+/// samples handed from the generator thread to the device callback, in
+/// frames of the machine's channels.
+struct Ring {
+    samples: Mutex<VecDeque<f32>>,
+    space: Condvar,
+    done: AtomicBool,
+    underruns: AtomicU64,
+}
+
+/// frames from the input device waiting to be mixed into the output,
+/// with the gain they're mixed at.
+struct Passthrough {
+    samples: Mutex<VecDeque<f32>>,
+    channels: usize,
+    gain: f32,
+}
+
+/// linear interpolation of frames from the machine's rate to the device
+/// rate.
+struct Resampler {
+    step: f64,
+    phase: f64,
+    last: Vec<f32>,
+    next: Vec<f32>,
+}
+
+impl Resampler {
+    fn new(pcm_hz: u64, device_hz: u32, channels: usize) -> Resampler {
+        Resampler {
+            step: pcm_hz as f64 / device_hz as f64,
+            phase: 0_f64,
+            last: vec![0_f32; channels],
+            next: vec![0_f32; channels],
+        }
+    }
+
+    /// the next frame at the device rate into `frame`, or false on
+    /// underrun.
+    fn next(&mut self, ring: &mut VecDeque<f32>, frame: &mut [f32]) -> bool {
+        while self.phase >= 1_f64 {
+            if ring.len() < self.next.len() {
+                return false;
+            }
+            self.last.copy_from_slice(&self.next);
+            for sample in self.next.iter_mut() {
+                *sample = ring.pop_front().unwrap();
+            }
+            self.phase -= 1_f64;
+        }
+        for (i, sample) in frame.iter_mut().enumerate() {
+            *sample = self.last[i] + (self.next[i] - self.last[i]) * self.phase as f32;
+        }
+        self.phase += self.step;
+
+        true
+    }
+}
+
+/// play the machine through the default output device until it has
+/// finished, or forever, calling `between_chunks` on it before each
+/// chunk, with the default input mixed in if the config has a
+/// passthrough gain. hands the machine back afterwards.
+pub fn play<F>(mut machine: HarmonyMachine, mut between_chunks: F) -> io::Result<HarmonyMachine>
+    where F: FnMut(&mut HarmonyMachine) + Send + 'static
+{
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no output device"))?;
+    let (pcm_hz, channels) = (machine.config().pcm_hz, machine.config().channels as usize);
+    let (config, format) = negotiate(&device, pcm_hz)?;
+    let input = match machine.config().passthrough {
+        Some(gain) => Some(capture(config.sample_rate, gain as f32)?),
+        None => None,
+    };
+    let passthrough = input.as_ref().map(|(_, passthrough)| passthrough.clone());
+    let ring = Arc::new(Ring {
+        samples: Mutex::new(VecDeque::with_capacity(RING_FRAMES * channels)),
+        space: Condvar::new(),
+        done: AtomicBool::new(false),
+        underruns: AtomicU64::new(0),
+    });
+
+    let generator = {
+        let ring = ring.clone();
+        thread::spawn(move || {
+            let mut chunk = vec![0_f64; CHUNK_FRAMES * channels];
+            loop {
+                between_chunks(&mut machine);
+                let left = machine.samples_left();
+                if left == Some(0) {
+                    break;
+                }
+                let frames = left.map_or(CHUNK_FRAMES, |left| CHUNK_FRAMES.min(left as usize));
+                let len = frames * channels;
+                machine.next_chunk(&mut chunk[..len]);
+
+                let mut buffered = ring.samples.lock().unwrap();
+                while buffered.len() + len > RING_FRAMES * channels {
+                    buffered = ring.space.wait(buffered).unwrap();
+                }
+                buffered.extend(chunk[..len].iter().map(|&s| s as f32));
+            }
+            ring.done.store(true, Ordering::SeqCst);
+            machine
+        })
+    };
+
+    let stream = match format {
+        SampleFormat::F32 => stream::<f32>(&device, &config, pcm_hz, channels, ring.clone(), passthrough),
+        SampleFormat::I16 => stream::<i16>(&device, &config, pcm_hz, channels, ring.clone(), passthrough),
+        SampleFormat::U16 => stream::<u16>(&device, &config, pcm_hz, channels, ring.clone(), passthrough),
+        other => Err(io::Error::other(format!("unsupported sample format {}", other))),
+    }?;
+    stream.play().map_err(io::Error::other)?;
+    if let Some((ref input, _)) = input {
+        input.play().map_err(io::Error::other)?;
+    }
+
+    let machine = generator.join().expect("generator thread panicked");
+    while !ring.samples.lock().unwrap().is_empty() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(stream);
+    drop(input);
+
+    let underruns = ring.underruns.load(Ordering::SeqCst);
+    if underruns > 0 {
+        eprintln!("playback: {} buffer underruns", underruns);
+    }
+
+    Ok(machine)
+}
+
+/// a config at `pcm_hz` if the device has one, preferring float samples,
+/// else the device default to be resampled to.
+fn negotiate(device: &Device, pcm_hz: u64) -> io::Result<(StreamConfig, SampleFormat)> {
+    let mut at_rate: Vec<_> = device.supported_output_configs()
+                                    .map_err(io::Error::other)?
+                                    .filter_map(|range| range.try_with_sample_rate(SampleRate(pcm_hz as u32)))
+                                    .collect();
+    at_rate.sort_by_key(|config| config.sample_format() != SampleFormat::F32);
+    let supported = match at_rate.into_iter().next() {
+        Some(config) => config,
+        None => device.default_output_config().map_err(io::Error::other)?,
+    };
+
+    Ok((supported.config(), supported.sample_format()))
+}
+
+/// an input stream from the default input device at `rate`, filling a
+/// passthrough to be mixed in at `gain`.
+fn capture(rate: SampleRate, gain: f32) -> io::Result<(Stream, Arc<Passthrough>)> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no input device"))?;
+    let mut at_rate: Vec<_> = device.supported_input_configs()
+                                    .map_err(io::Error::other)?
+                                    .filter_map(|range| range.try_with_sample_rate(rate))
+                                    .collect();
+    at_rate.sort_by_key(|config| config.sample_format() != SampleFormat::F32);
+    let supported = at_rate.into_iter().next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Unsupported, format!("input device can't run at the output's {} Hz", rate.0))
+    })?;
+    let (config, format) = (supported.config(), supported.sample_format());
+    let passthrough = Arc::new(Passthrough {
+        samples: Mutex::new(VecDeque::with_capacity(PASSTHROUGH_FRAMES * config.channels as usize)),
+        channels: config.channels as usize,
+        gain,
+    });
+
+    let stream = match format {
+        SampleFormat::F32 => input_stream::<f32>(&device, &config, passthrough.clone()),
+        SampleFormat::I16 => input_stream::<i16>(&device, &config, passthrough.clone()),
+        SampleFormat::U16 => input_stream::<u16>(&device, &config, passthrough.clone()),
+        other => Err(io::Error::other(format!("unsupported input sample format {}", other))),
+    }?;
+
+    Ok((stream, passthrough))
+}
+
+/// an input stream pushing its frames onto `passthrough`, dropping the
+/// oldest past PASSTHROUGH_FRAMES.
+fn input_stream<T>(device: &Device, config: &StreamConfig, passthrough: Arc<Passthrough>) -> io::Result<Stream>
+    where T: SizedSample, f32: FromSample<T>
+{
+    device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            let mut buffered = passthrough.samples.lock().unwrap();
+            buffered.extend(data.iter().map(|&sample| sample.to_sample::<f32>()));
+            let excess = buffered.len().saturating_sub(PASSTHROUGH_FRAMES * passthrough.channels);
+            buffered.drain(..excess - excess % passthrough.channels);
+        },
+        |e| eprintln!("passthrough error: {}", e),
+        None,
+    ).map_err(io::Error::other)
+}
+
+/// an output stream filling every device channel from the ring's frames
+/// of `channels`, with silence when the generator falls behind. stereo
+/// goes to the first two device channels and is mixed down for a mono
+/// device; mono goes to every channel. input frames waiting in
+/// `passthrough` are added on top, a mono input into every channel.
+fn stream<T>(device: &Device, config: &StreamConfig, pcm_hz: u64, channels: usize, ring: Arc<Ring>,
+             passthrough: Option<Arc<Passthrough>>) -> io::Result<Stream>
+    where T: SizedSample + FromSample<f32>
+{
+    let device_channels = config.channels as usize;
+    let mut resampler = Resampler::new(pcm_hz, config.sample_rate.0, channels);
+    let mut frame = vec![0_f32; channels];
+    let mut heard = vec![];
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut buffered = ring.samples.lock().unwrap();
+            let mut input = passthrough.as_ref().map(|passthrough| (passthrough, passthrough.samples.lock().unwrap()));
+            let mut underrun = false;
+            for out in data.chunks_mut(device_channels) {
+                if !resampler.next(&mut buffered, &mut frame) {
+                    underrun = true;
+                    frame.iter_mut().for_each(|sample| *sample = 0_f32);
+                }
+                heard.clear();
+                if let Some((passthrough, ref mut input)) = input {
+                    if input.len() >= passthrough.channels {
+                        heard.extend(input.drain(..passthrough.channels).map(|sample| sample * passthrough.gain));
+                    }
+                }
+                for (i, sample) in out.iter_mut().enumerate() {
+                    let generated = match (channels, device_channels) {
+                        (1, _) => frame[0],
+                        (_, 1) => frame.iter().sum::<f32>() / channels as f32,
+                        _ => frame.get(i).cloned().unwrap_or(0_f32),
+                    };
+                    let input = if heard.is_empty() { 0_f32 } else { heard[i % heard.len()] };
+                    *sample = T::from_sample(generated + input);
+                }
+            }
+            if underrun && !ring.done.load(Ordering::SeqCst) {
+                ring.underruns.fetch_add(1, Ordering::SeqCst);
+            }
+            ring.space.notify_one();
+        },
+        |e| eprintln!("playback error: {}", e),
+        None,
+    ).map_err(io::Error::other)
+}
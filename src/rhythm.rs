@@ -0,0 +1,52 @@
+//! how long each noteset lasts, picked from the config's durations by
+//! their own novelty against a memory of the durations already played.
+
+use std::slice;
+
+use {judge_novelty, Frac, Memory};
+
+/// what each remembered duration's familiarity is multiplied by every
+/// chord change, what choosing one adds to it, and the familiarity the
+/// choice steers toward.
+pub static RHYTHM_DECAY: f64 = 0.75_f64;
+pub static RHYTHM_INCREMENT: f64 = 0.1_f64;
+pub static RHYTHM_TARGET: f64 = 0.03_f64;
+
+/// judge a duration based on familiarity & novelty balance, against the
+/// durations remembered.
+/// range: floats in [0, 1] and lower is better.
+pub fn judge_rhythm(duration: &Frac, memory: &Memory) -> f64 {
+    judge_novelty(slice::from_ref(duration), memory, RHYTHM_TARGET, 1_f64)
+}
+
+/// the durations played, so the next can be judged against them.
+pub struct Rhythm {
+    memory: Memory,
+}
+
+impl Rhythm {
+    pub fn new() -> Rhythm {
+        Rhythm { memory: Memory::new() }
+    }
+
+    /// the best judged of `durations`, in steps, remembered as played.
+    /// 1/1 if there are none.
+    pub fn next(&mut self, durations: &[Frac]) -> Frac {
+        for val in self.memory.values_mut() {
+            *val *= RHYTHM_DECAY;
+        }
+        let duration = durations.iter()
+                                .min_by(|x, y| judge_rhythm(x, &self.memory).partial_cmp(&judge_rhythm(y, &self.memory)).unwrap())
+                                .cloned()
+                                .unwrap_or(Frac(1, 1));
+        *self.memory.entry(duration.clone()).or_insert(0_f64) += RHYTHM_INCREMENT;
+
+        duration
+    }
+}
+
+impl Default for Rhythm {
+    fn default() -> Rhythm {
+        Rhythm::new()
+    }
+}
@@ -0,0 +1,1007 @@
+use std::str::FromStr;
+
+use analysis::chord_roughness;
+use {math, nearest_ratio, Config, Frac, Memory};
+
+pub static MAX_HARMONICS: u64 = 6;
+pub static WARMTH_FAMILIARITY: f64 = 0.1_f64;
+pub static PHRASE_STEPS: u64 = 16;
+pub static MIN_LEVEL: f64 = 0.4_f64;
+pub static ACCENT: f64 = 1.3_f64;
+pub static HEADROOM: f64 = 0.8_f64;
+pub static FADE_IN_SECS: f64 = 2_f64;
+/// how long the machine takes to fall silent once it's asked to finish.
+pub static FADE_OUT_SECS: f64 = 2_f64;
+/// raised-cosine ramp in milliseconds at the start of every tone that
+/// wasn't sounding at the same frequency last step; 0 turns it off.
+pub static DECLICK_MS: f64 = 5_f64;
+/// milliseconds a held tone takes to move to its new gain and harmonics
+/// at a step; 0 jumps.
+pub static TRANSITION_MS: f64 = 10_f64;
+/// level of a doubling relative to the note it copies.
+pub static DOUBLING_GAIN: f64 = 0.5_f64;
+pub static MOVEMENT_WEIGHT: f64 = 0.1_f64;
+/// the oscillator of the machine's own notes; voices pick their own.
+pub static OSCILLATOR: Oscillator = Oscillator::Harmonic { rolloff: 1_f64 };
+/// how many times a trill goes up to the upper neighbour.
+pub static TRILL_BEATS: u64 = 3;
+
+/// oscillator shapes, summed from partials up to a tone's harmonic
+/// count that stop short of nyquist, so they don't alias.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Oscillator {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+    /// every partial k at 1/k^rolloff.
+    Harmonic { rolloff: f64 },
+}
+
+impl Oscillator {
+    /// partial `k` as the sign and divisor of its amplitude, or None if
+    /// the shape doesn't have it.
+    fn partial(&self, k: u64) -> Option<(f64, f64)> {
+        let odd = k % 2 == 1;
+        match *self {
+            Oscillator::Sine => if k == 1 { Some((1_f64, 1_f64)) } else { None },
+            Oscillator::Square => if odd { Some((1_f64, k as f64)) } else { None },
+            Oscillator::Saw => Some((if odd { 1_f64 } else { -1_f64 }, k as f64)),
+            Oscillator::Triangle if odd => {
+                Some((if (k / 2).is_multiple_of(2) { 1_f64 } else { -1_f64 }, (k * k) as f64))
+            }
+            Oscillator::Triangle => None,
+            Oscillator::Harmonic { rolloff } => Some((1_f64, math::pow(k as f64, rolloff))),
+        }
+    }
+
+    /// the last of the first `harmonics` partials worth summing.
+    fn last_partial(&self, harmonics: u64) -> u64 {
+        match *self {
+            Oscillator::Sine => harmonics.min(1),
+            _ => harmonics,
+        }
+    }
+}
+
+/// the sub-oscillator for a step's tones: an octave under the lowest one
+/// at `level` of its gain, as (frequency, gain).
+pub fn sub_tone(tones: &[Tone], level: f64) -> Option<(f64, f64)> {
+    tones.iter()
+         .min_by(|x, y| x.freq.partial_cmp(&y.freq).unwrap())
+         .map(|lowest| (lowest.freq / 2_f64, lowest.gain * level))
+}
+
+/// ways of placing entering tones across the stereo field. held tones
+/// stay where they are.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Panning {
+    /// low notes to the left, high to the right.
+    ByPitch,
+    /// anywhere within config.pan_width.
+    Random,
+}
+
+impl FromStr for Panning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Panning, String> {
+        match s {
+            "by_pitch" => Ok(Panning::ByPitch),
+            "random" => Ok(Panning::Random),
+            _ => Err(format!("unknown panning {}, expected by_pitch or random", s)),
+        }
+    }
+}
+
+/// how the oscillators of a step play off one another.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interaction {
+    Off,
+    /// every tone restarts its cycle with the lowest tone's.
+    Sync,
+    /// every tone is phase modulated by the other tone it makes the
+    /// simplest ratio with.
+    CrossModulation,
+}
+
+impl FromStr for Interaction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Interaction, String> {
+        match s {
+            "off" => Ok(Interaction::Off),
+            "sync" => Ok(Interaction::Sync),
+            "cross_modulation" => Ok(Interaction::CrossModulation),
+            _ => Err(format!("unknown interaction {}, expected off, sync or cross_modulation", s)),
+        }
+    }
+}
+
+/// the tone another plays off, picked for the config's interaction.
+#[derive(Clone, Copy)]
+pub enum Partner {
+    /// restart the tone's cycle with this frequency's.
+    Sync(f64),
+    /// phase modulate the tone by this frequency, by `index` radians at
+    /// the peak.
+    CrossModulation { freq: f64, index: f64 },
+}
+/// how tones masked by louder tones within a few bark are turned down,
+/// by how far they fall under the masking threshold, up to max_cut_db.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Masking {
+    /// how fast masking falls off per bark, toward higher and lower
+    /// tones.
+    pub slope_up_db: f64,
+    pub slope_down_db: f64,
+    /// how far under the masker's level the threshold sits at the
+    /// masker.
+    pub offset_db: f64,
+    pub max_cut_db: f64,
+}
+
+impl Default for Masking {
+    fn default() -> Masking {
+        Masking { slope_up_db: 10_f64, slope_down_db: 25_f64, offset_db: 6_f64, max_cut_db: 12_f64 }
+    }
+}
+
+/// master eq: gains in dB for the bands below low_shelf_hz, between the
+/// two shelves and above high_shelf_hz. all zero leaves the bus
+/// untouched.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EqBands {
+    pub low_shelf_hz: f64,
+    pub high_shelf_hz: f64,
+    pub low_gain_db: f64,
+    pub mid_gain_db: f64,
+    pub high_gain_db: f64,
+}
+
+impl Default for EqBands {
+    fn default() -> EqBands {
+        EqBands { low_shelf_hz: 250_f64, high_shelf_hz: 4000_f64, low_gain_db: 0_f64, mid_gain_db: 0_f64, high_gain_db: 0_f64 }
+    }
+}
+
+pub fn sine_wave(freq: f64, step: u64, pcm_hz: u64) -> f64 {
+    math::sin(2.0*::std::f64::consts::PI*(step as f64)*freq/(pcm_hz as f64))
+}
+
+/// harmonic count for each note: with no oscillator chosen, on
+/// OSCILLATOR, familiar notes sound warm with few harmonics and novel
+/// ones bright with up to MAX_HARMONICS. a chosen shape keeps every
+/// partial up to nyquist, so it sounds as chosen.
+pub fn timbre(noteset: &[Frac], memory: &Memory, oscillator: Option<&Oscillator>) -> Vec<u64> {
+    noteset.iter().map(|note| {
+        if oscillator.is_some() {
+            return u64::MAX;
+        }
+        let &familiarity = memory.get(note).unwrap_or(&0_f64);
+        let brightness = 1_f64/math::exp(familiarity/WARMTH_FAMILIARITY);
+        1 + ((MAX_HARMONICS - 1) as f64 * brightness).round() as u64
+    }).collect()
+}
+
+/// a tone made of the first `harmonics` partials of `oscillator`,
+/// normalized to full scale and skipping partials above nyquist.
+pub fn harmonic_wave(oscillator: &Oscillator, freq: f64, harmonics: u64, step: u64, pcm_hz: u64) -> f64 {
+    let phase = 2.0*::std::f64::consts::PI*(step as f64)*freq/(pcm_hz as f64);
+    harmonic_sum(oscillator, phase, freq, harmonics, pcm_hz, |k| sine_wave(freq * (k as f64), step, pcm_hz))
+}
+
+/// partials of a tone at the given phase in radians, weighted like
+/// harmonic_wave.
+pub fn harmonic_phase_wave(oscillator: &Oscillator, phase: f64, freq: f64, harmonics: u64, pcm_hz: u64) -> f64 {
+    harmonic_sum(oscillator, phase, freq, harmonics, pcm_hz, |k| math::sin(phase * (k as f64)))
+}
+
+/// sums the partials below nyquist, taking the first MAX_HARMONICS from
+/// `sine` and the rest two at a time from
+/// sin((k+2)x) = 2cos(2x)sin(kx) - sin((k-2)x), so a low saw's hundreds
+/// of partials don't cost a sin call each.
+fn harmonic_sum<F: Fn(u64) -> f64>(oscillator: &Oscillator, phase: f64, freq: f64, harmonics: u64, pcm_hz: u64, sine: F) -> f64 {
+    let below_nyquist = (((pcm_hz as f64) / 2_f64 / freq).ceil() as u64).saturating_sub(1);
+    let partials = oscillator.last_partial(harmonics).min(below_nyquist);
+    let mut sum = 0_f64;
+    let mut norm = 0_f64;
+    let mut add = |k: u64, sine: f64| {
+        if let Some((sign, divisor)) = oscillator.partial(k) {
+            sum += sign * sine / divisor;
+            norm += 1_f64 / divisor;
+        }
+    };
+    // the sines of the four partials before k
+    let mut sines = (0_f64, 0_f64, 0_f64, 0_f64);
+    for k in 1..=partials.min(MAX_HARMONICS) {
+        let next = sine(k);
+        sines = (sines.1, sines.2, sines.3, next);
+        add(k, next);
+    }
+    let twice_cos = 2_f64 * math::cos(2_f64 * phase);
+    let mut k = MAX_HARMONICS + 1;
+    while k <= partials {
+        let (a, b, c, d) = sines;
+        let (next, after) = (twice_cos * c - a, twice_cos * d - b);
+        sines = (c, d, next, after);
+        add(k, next);
+        if k < partials {
+            add(k + 1, after);
+        }
+        k += 2;
+    }
+
+    if norm > 0_f64 { sum / norm } else { 0_f64 }
+}
+
+/// a euclidean rhythm a note of the noteset plays to, counted in steps.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Gate {
+    pub hits: u64,
+    pub slots: u64,
+    #[serde(default)]
+    pub rotation: u64,
+}
+
+/// an extra copy of a note, e.g. note 0 at ratio 2.0 doubles the first
+/// note of the noteset an octave up.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Doubling {
+    /// position of the note in the noteset.
+    pub note: usize,
+    /// frequency ratio of the copy to the note.
+    pub ratio: f64,
+}
+
+/// what a set of tones is made with: the machine's own settings or a
+/// voice's, and the config the machine plays by.
+pub struct Part<'a> {
+    /// the shape chosen, or none for OSCILLATOR darkened by familiarity.
+    pub oscillator: Option<Oscillator>,
+    /// a gate for each note of the noteset; notes past the end of the
+    /// list always play.
+    pub note_gates: &'a [Gate],
+    pub doublings: &'a [Doubling],
+    pub config: &'a Config,
+}
+
+/// a note as it sounds for one step.
+pub struct Tone {
+    pub freq: f64,
+    pub harmonics: u64,
+    pub gain: f64,
+    /// the tone this one syncs to or is modulated by, if any.
+    pub partner: Option<Partner>,
+    /// whether the tone enters this step and gets the DECLICK_MS ramp.
+    pub onset: bool,
+    pub glide: Option<Glide>,
+    pub ornament: Option<Ornament>,
+    /// sample the tone entered at, which its envelope is timed from.
+    pub entered: u64,
+    /// how a held tone sounded at the end of the last step.
+    pub from: Option<Transition>,
+    pub oscillator: Oscillator,
+    /// what the tone is shaped by, from the step it enters until its
+    /// release has faded out after it stops.
+    pub envelope: Adsr,
+    /// place in the stereo field, from -1 (left) to 1 (right).
+    pub pan: f64,
+    /// what the tone is part of, for routing: 0 for the machine's own
+    /// notes, then one per voice.
+    pub stem: usize,
+}
+
+/// gain, scaled to this step's mix, and harmonics a held tone moves
+/// from over TRANSITION_MS.
+pub struct Transition {
+    pub gain: f64,
+    pub harmonics: u64,
+}
+
+/// attack, decay and release times and the level held in between.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Adsr {
+    pub attack_ms: f64,
+    pub decay_ms: f64,
+    pub sustain: f64,
+    pub release_ms: f64,
+}
+
+impl Default for Adsr {
+    fn default() -> Adsr {
+        Adsr { attack_ms: 10_f64, decay_ms: 150_f64, sustain: 0.8_f64, release_ms: 120_f64 }
+    }
+}
+
+impl Adsr {
+    /// level of a tone that entered `secs` ago and is still sounding.
+    pub fn level(&self, secs: f64) -> f64 {
+        let (attack, decay) = (self.attack_ms / 1000_f64, self.decay_ms / 1000_f64);
+        if secs < attack {
+            secs / attack
+        } else if secs < attack + decay {
+            1_f64 - (1_f64 - self.sustain) * (secs - attack) / decay
+        } else {
+            self.sustain
+        }
+    }
+
+    /// level of a tone released at `from` `secs` ago.
+    pub fn release(&self, from: f64, secs: f64) -> f64 {
+        let release = self.release_ms / 1000_f64;
+        if secs < release { from * (1_f64 - secs / release) } else { 0_f64 }
+    }
+}
+
+/// a tone that has stopped, fading out over the envelope's release into
+/// the steps after it.
+pub struct Release {
+    pub tone: Tone,
+    /// first sample of the step the tone last sounded in, and of its
+    /// release.
+    pub step_start: u64,
+    pub start: u64,
+    /// envelope level it was released at.
+    pub level: f64,
+    /// tones it was mixed with, which it is still scaled by.
+    pub voices: usize,
+}
+
+/// an exponential sweep into a tone's frequency over a step, with phase
+/// carried on from the tone it leaves.
+pub struct Glide {
+    pub from: f64,
+    /// sample the sweep starts at, and how many samples it takes.
+    pub start: u64,
+    pub samples: u64,
+}
+
+/// ways to decorate a held tone with its neighbours.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrnamentKind {
+    /// alternate with the upper neighbour TRILL_BEATS times.
+    Trill,
+    /// upper neighbour, the tone, lower neighbour, the tone.
+    Turn,
+}
+
+impl FromStr for OrnamentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OrnamentKind, String> {
+        match s {
+            "trill" => Ok(OrnamentKind::Trill),
+            "turn" => Ok(OrnamentKind::Turn),
+            _ => Err(format!("unknown ornament {}, expected trill or turn", s)),
+        }
+    }
+}
+
+/// a trill or turn at the start of a step. both end on the lower
+/// neighbour held just long enough to give back the phase the upper
+/// ones gained, so the tone lands back in phase with itself.
+pub struct Ornament {
+    pub kind: OrnamentKind,
+    /// frequency ratio of the upper neighbour; the lower is its inverse.
+    pub neighbor: f64,
+    /// samples per ornament note.
+    pub unit: f64,
+}
+
+impl Ornament {
+    /// the `i`th note as (ratio to the tone, length in units).
+    fn note(&self, i: u64) -> Option<(f64, f64)> {
+        let r = self.neighbor;
+        match self.kind {
+            OrnamentKind::Trill if i < 2 * TRILL_BEATS => Some(if i.is_multiple_of(2) { (r, 1_f64) } else { (1_f64, 1_f64) }),
+            OrnamentKind::Trill if i == 2 * TRILL_BEATS => Some((1_f64 / r, r * TRILL_BEATS as f64)),
+            OrnamentKind::Turn if i < 4 => Some([(r, 1_f64), (1_f64, 1_f64), (1_f64 / r, r), (1_f64, 1_f64)][i as usize]),
+            _ => None,
+        }
+    }
+
+    /// length of the whole ornament in units.
+    pub fn units(&self) -> f64 {
+        (0..).map_while(|i| self.note(i)).map(|(_, units)| units).sum()
+    }
+
+    /// cycles of `freq` gained over the plain tone `progress` samples in.
+    fn deviation(&self, freq: f64, progress: u64) -> f64 {
+        let mut start = 0_f64;
+        let mut cycles = 0_f64;
+        let mut i = 0;
+        while let Some((ratio, units)) = self.note(i) {
+            let length = units * self.unit;
+            let elapsed = (progress as f64 - start).min(length);
+            if elapsed <= 0_f64 {
+                break;
+            }
+            cycles += freq * (ratio - 1_f64) * elapsed;
+            start += length;
+            i += 1;
+        }
+
+        cycles
+    }
+}
+
+/// overall level of a step: a rise and fall over each phrase, lifted by
+/// the novelty score of the notes chosen for the step.
+pub fn dynamics(step: u64, novelty: f64) -> f64 {
+    let phrase_pos = (step % PHRASE_STEPS) as f64 + 0.5_f64;
+    let arc = math::sin(::std::f64::consts::PI * phrase_pos / (PHRASE_STEPS as f64));
+    MIN_LEVEL + (1_f64 - MIN_LEVEL) * (arc + novelty) / 2_f64
+}
+
+/// cost of a voicing: roughness between all pairs plus how far each
+/// tone is from the nearest tone of the last voicing, in octaves.
+fn voicing_cost(voiced: &[f64], last: &[f64]) -> f64 {
+    let mut movement = 0_f64;
+    for &f in voiced {
+        let nearest = last.iter().map(|&g| math::log2(f / g).abs()).fold(f64::INFINITY, f64::min);
+        if nearest.is_finite() {
+            movement += nearest;
+        }
+    }
+
+    chord_roughness(voiced) + MOVEMENT_WEIGHT * movement
+}
+
+/// frequencies for a noteset, each moved up to `octaves` octaves from
+/// where its ratio puts it so the voicing minimizes voicing_cost.
+pub fn voicing(base_note: f64, noteset: &[Frac], last: &[f64], octaves: u32) -> Vec<f64> {
+    let freqs: Vec<f64> = noteset.iter()
+                                 .map(|&Frac(a, b)| (base_note / (b as f64)) * (a as f64))
+                                 .collect();
+    let choices = (2 * octaves + 1) as usize;
+
+    let mut best = freqs.clone();
+    let mut best_cost = f64::INFINITY;
+    for combo in 0..choices.pow(freqs.len() as u32) {
+        let mut rest = combo;
+        let voiced: Vec<f64> = freqs.iter().map(|&f| {
+            let octave = (rest % choices) as i32 - octaves as i32;
+            rest /= choices;
+            f * math::pow(2_f64, octave as f64)
+        }).collect();
+        let cost = voicing_cost(&voiced, last);
+        if cost < best_cost {
+            best = voiced;
+            best_cost = cost;
+        }
+    }
+
+    best
+}
+
+/// turn a noteset into tones of `part` at the given level, accenting
+/// notes that weren't sounding in the previous step, leaving out gated
+/// notes and adding doublings.
+pub fn tones(noteset: &[Frac], freqs: &[f64], previous: &[Frac], memory: &Memory, level: f64, step: u64,
+             part: &Part) -> Vec<Tone> {
+    let mut tones = vec![];
+    let harmonics = timbre(noteset, memory, part.oscillator.as_ref());
+    let oscillator = part.oscillator.unwrap_or(OSCILLATOR);
+    for (i, (note, &freq)) in noteset.iter().zip(freqs).enumerate() {
+        if !gate_open(part.note_gates, i, step) {
+            continue;
+        }
+        let accent = if previous.contains(note) { 1_f64 } else { ACCENT };
+        let tone = Tone {
+            freq,
+            harmonics: harmonics[i],
+            gain: level * accent,
+            partner: None,
+            onset: true,
+            glide: None,
+            ornament: None,
+            entered: 0,
+            from: None,
+            oscillator,
+            envelope: part.config.envelope,
+            pan: 0_f64,
+            stem: 0,
+        };
+        for doubling in part.doublings.iter().filter(|doubling| doubling.note == i) {
+            tones.push(Tone {
+                freq: tone.freq * doubling.ratio,
+                harmonics: tone.harmonics,
+                gain: tone.gain * DOUBLING_GAIN,
+                partner: None,
+                onset: true,
+                glide: None,
+                ornament: None,
+                entered: 0,
+                from: None,
+                oscillator,
+                envelope: part.config.envelope,
+                pan: 0_f64,
+                stem: 0,
+            });
+        }
+        tones.push(tone);
+    }
+
+    let spaced = space(tones, part.config.min_spacing_cents);
+    let tones = match part.config.masking {
+        Some(ref masking) => mask(spaced, masking),
+        None => spaced,
+    };
+    pair(tones, part.config)
+}
+
+/// pick each tone's partner for the config's interaction: the lowest
+/// tone to sync to, or the tone it makes the simplest ratio with, up to
+/// pair_limit, to be modulated by. tones arrive sorted low to high.
+fn pair(mut tones: Vec<Tone>, config: &Config) -> Vec<Tone> {
+    let freqs: Vec<f64> = tones.iter().map(|tone| tone.freq).collect();
+    for (i, tone) in tones.iter_mut().enumerate() {
+        tone.partner = match config.interaction {
+            Interaction::Off => None,
+            Interaction::Sync => if i > 0 { Some(Partner::Sync(freqs[0])) } else { None },
+            Interaction::CrossModulation => {
+                freqs.iter().enumerate()
+                     .filter(|&(j, _)| j != i)
+                     .min_by_key(|&(_, &f)| {
+                         let Frac(a, b) = nearest_ratio(tone.freq / f, config.pair_limit);
+                         a as u128 * b as u128
+                     })
+                     .map(|(_, &f)| Partner::CrossModulation { freq: f, index: config.cross_mod_index })
+            }
+        };
+    }
+
+    tones
+}
+
+/// critical band rate of a frequency, after zwicker.
+fn bark(freq: f64) -> f64 {
+    13_f64 * math::atan(0.00076_f64 * freq) + 3.5_f64 * math::atan((freq / 7500_f64) * (freq / 7500_f64))
+}
+
+fn decibels(gain: f64) -> f64 {
+    20_f64 * math::log2(gain) / math::log2(10_f64)
+}
+
+/// cut each tone by how far it falls under the masking threshold of the
+/// others, with the threshold spreading in a triangle over bark.
+fn mask(tones: Vec<Tone>, masking: &Masking) -> Vec<Tone> {
+    let levels: Vec<(f64, f64)> = tones.iter().map(|tone| (bark(tone.freq), decibels(tone.gain))).collect();
+
+    tones.into_iter().enumerate().map(|(i, mut tone)| {
+        let (z, level) = levels[i];
+        let threshold = levels.iter().enumerate()
+                              .filter(|&(j, _)| j != i)
+                              .map(|(_, &(masker_z, masker_level))| {
+                                  let distance = z - masker_z;
+                                  let slope = if distance > 0_f64 { masking.slope_up_db } else { masking.slope_down_db };
+                                  masker_level - masking.offset_db - slope * distance.abs()
+                              })
+                              .fold(f64::NEG_INFINITY, f64::max);
+        let cut = (threshold - level).clamp(0_f64, masking.max_cut_db);
+        tone.gain *= math::pow(10_f64, -cut / 20_f64);
+        tone
+    }).collect()
+}
+
+/// raise tones by octaves until each is at least `min_cents` away from
+/// every lower one.
+fn space(mut tones: Vec<Tone>, min_cents: f64) -> Vec<Tone> {
+    let min_ratio = math::pow(2_f64, min_cents / 1200_f64);
+    tones.sort_by(|x, y| x.freq.partial_cmp(&y.freq).unwrap());
+
+    let mut spaced: Vec<Tone> = vec![];
+    for mut tone in tones {
+        while spaced.iter().any(|placed| {
+            let interval = tone.freq / placed.freq;
+            interval < min_ratio && interval > 1_f64 / min_ratio
+        }) {
+            tone.freq *= 2_f64;
+        }
+        spaced.push(tone);
+    }
+
+    spaced
+}
+
+/// euclidean rhythm: `hits` onsets spread as evenly as possible over
+/// `slots`, rotated right by `rotation`.
+pub fn euclidean(hits: u64, slots: u64, rotation: u64) -> Vec<bool> {
+    (0..slots).map(|i| {
+        let j = (i + slots - rotation % slots) % slots;
+        (j * hits) % slots < hits
+    }).collect()
+}
+
+/// whether the note at `slot` in the noteset sounds on this step.
+fn gate_open(gates: &[Gate], slot: usize, step: u64) -> bool {
+    match gates.get(slot) {
+        Some(gate) if gate.slots > 0 => euclidean(gate.hits, gate.slots, gate.rotation)[(step % gate.slots) as usize],
+        _ => true,
+    }
+}
+
+/// clear the onset of tones that carry on at the same frequency from
+/// the previous step, so only entering tones are ramped, and time the
+/// envelope of entering tones from `start`. held tones move from how
+/// they last sounded, and stay where they were panned. a glide ends out of phase with its target, so
+/// what follows it counts as entering.
+pub fn mark_onsets(tones: &mut [Tone], previous: &[Tone], start: u64) {
+    let rescale = tones.len() as f64 / previous.len().max(1) as f64;
+    for tone in tones {
+        let held = previous.iter().find(|held| held.freq == tone.freq && held.glide.is_none());
+        tone.onset = held.is_none();
+        tone.entered = held.map_or(start, |held| held.entered);
+        tone.from = held.map(|held| Transition { gain: held.gain * rescale, harmonics: held.harmonics });
+        tone.pan = held.map_or(tone.pan, |held| held.pan);
+    }
+}
+
+/// the tones of the previous step that don't carry on into `tones`,
+/// released at `start` from wherever their envelope had got to.
+pub fn releases(previous: Vec<Tone>, tones: &[Tone], start: u64, step_samples: u64, pcm_hz: u64) -> Vec<Release> {
+    let voices = previous.len();
+    previous.into_iter()
+            .filter(|held| held.glide.is_some() || !tones.iter().any(|tone| tone.freq == held.freq))
+            .map(|tone| {
+                let level = tone.envelope.level(start.wrapping_sub(tone.entered) as f64 / pcm_hz as f64);
+                Release { tone, step_start: start.wrapping_sub(step_samples), start, level, voices }
+            })
+            .collect()
+}
+
+/// phase in radians of a gliding tone `progress` samples into its sweep.
+/// past the end of the sweep it carries on at `freq`.
+fn glide_phase(glide: &Glide, freq: f64, progress: u64, pcm_hz: u64) -> f64 {
+    let ratio = freq / glide.from;
+    let sweeping = progress.min(glide.samples);
+    let swept = math::pow(ratio, (sweeping as f64) / (glide.samples as f64)) - 1_f64;
+    let cycles = glide.from * (glide.start as f64)
+                 + glide.from * (glide.samples as f64) * swept / (math::log2(ratio) * ::std::f64::consts::LN_2)
+                 + freq * (progress - sweeping) as f64;
+
+    2.0*::std::f64::consts::PI*cycles/(pcm_hz as f64)
+}
+
+/// how far a held tone has moved from how it last sounded, in [0, 1],
+/// `progress` samples into its step.
+fn transition_progress(progress: u64, pcm_hz: u64) -> f64 {
+    let samples = TRANSITION_MS / 1000_f64 * (pcm_hz as f64);
+    if (progress as f64) < samples { (progress as f64) / samples } else { 1_f64 }
+}
+
+/// gain of an entering tone `progress` samples into its step.
+fn declick(progress: u64, pcm_hz: u64) -> f64 {
+    let ramp = DECLICK_MS / 1000_f64 * (pcm_hz as f64);
+    if (progress as f64) < ramp {
+        (1_f64 - math::cos(::std::f64::consts::PI * (progress as f64) / ramp)) / 2_f64
+    } else {
+        1_f64
+    }
+}
+
+/// a tone's waveform with `harmonics` partials at sample `step`,
+/// `progress` samples into its step.
+fn tone_wave(tone: &Tone, harmonics: u64, step: u64, progress: u64, pcm_hz: u64) -> f64 {
+    match (&tone.glide, &tone.ornament, tone.partner) {
+        (Some(glide), _, _) => {
+            let phase = glide_phase(glide, tone.freq, progress, pcm_hz);
+            harmonic_phase_wave(&tone.oscillator, phase, tone.freq.max(glide.from), harmonics, pcm_hz)
+        }
+        (_, Some(ornament), _) => {
+            let cycles = (step as f64) * tone.freq + ornament.deviation(tone.freq, progress);
+            let phase = 2.0*::std::f64::consts::PI*cycles/(pcm_hz as f64);
+            harmonic_phase_wave(&tone.oscillator, phase, tone.freq * ornament.neighbor, harmonics, pcm_hz)
+        }
+        (_, _, Some(Partner::Sync(master))) => {
+            let since_reset = ((step as f64) * master / (pcm_hz as f64)).fract() / master;
+            let phase = 2.0*::std::f64::consts::PI*since_reset*tone.freq;
+            harmonic_phase_wave(&tone.oscillator, phase, tone.freq, harmonics, pcm_hz)
+        }
+        (_, _, Some(Partner::CrossModulation { freq: modulator, index })) => {
+            let phase = 2.0*::std::f64::consts::PI*(step as f64)*tone.freq/(pcm_hz as f64)
+                        + index * sine_wave(modulator, step, pcm_hz);
+            harmonic_phase_wave(&tone.oscillator, phase, tone.freq, harmonics, pcm_hz)
+        }
+        _ => harmonic_wave(&tone.oscillator, tone.freq, harmonics, step, pcm_hz),
+    }
+}
+
+/// one tone at sample `step`, `progress` samples into the step, before
+/// it is mixed.
+fn tone_sample(tone: &Tone, step: u64, progress: u64, pcm_hz: u64) -> f64 {
+    let ramp = if tone.onset { declick(progress, pcm_hz) } else { 1_f64 };
+    let envelope = tone.envelope.level(step.wrapping_sub(tone.entered) as f64 / pcm_hz as f64);
+    let moved = transition_progress(progress, pcm_hz);
+    let (gain, wave) = match tone.from {
+        Some(ref from) if moved < 1_f64 => {
+            let wave = tone_wave(tone, tone.harmonics, step, progress, pcm_hz);
+            let from_wave = if from.harmonics == tone.harmonics {
+                wave
+            } else {
+                tone_wave(tone, from.harmonics, step, progress, pcm_hz)
+            };
+            (from.gain + (tone.gain - from.gain) * moved, from_wave + (wave - from_wave) * moved)
+        }
+        _ => (tone.gain, tone_wave(tone, tone.harmonics, step, progress, pcm_hz)),
+    };
+
+    gain * ramp * envelope * wave
+}
+
+/// the tones mixed at sample `step`, `progress` samples into the step.
+pub fn sine_waves(tones: &[Tone], step: u64, progress: u64, pcm_hz: u64) -> f64 {
+    if tones.is_empty() {
+        return 0_f64;
+    }
+
+    let mut sum = 0_f64;
+    for tone in tones {
+        sum += tone_sample(tone, step, progress, pcm_hz);
+    }
+
+    sum / (tones.len() as f64)
+}
+
+/// sine_waves as a left and right pair, each tone at its pan.
+pub fn stereo_waves(tones: &[Tone], step: u64, progress: u64, pcm_hz: u64) -> (f64, f64) {
+    if tones.is_empty() {
+        return (0_f64, 0_f64);
+    }
+
+    let (mut left, mut right) = (0_f64, 0_f64);
+    for tone in tones {
+        let sample = tone_sample(tone, step, progress, pcm_hz);
+        let (l, r) = pan_gains(tone.pan);
+        left += l * sample;
+        right += r * sample;
+    }
+
+    (left / (tones.len() as f64), right / (tones.len() as f64))
+}
+
+/// one released tone at sample `step`, scaled as it was in its chord.
+fn release_sample(release: &Release, step: u64, pcm_hz: u64) -> f64 {
+    let envelope = release.tone.envelope.release(release.level, step.wrapping_sub(release.start) as f64 / pcm_hz as f64);
+    let wave = tone_wave(&release.tone, release.tone.harmonics, step, step.wrapping_sub(release.step_start), pcm_hz);
+    release.tone.gain * envelope * wave / (release.voices as f64)
+}
+
+/// released tones fading out at sample `step`.
+pub fn release_waves(releases: &[Release], step: u64, pcm_hz: u64) -> f64 {
+    releases.iter().map(|release| release_sample(release, step, pcm_hz)).sum()
+}
+
+/// release_waves as a left and right pair.
+pub fn stereo_release_waves(releases: &[Release], step: u64, pcm_hz: u64) -> (f64, f64) {
+    releases.iter().fold((0_f64, 0_f64), |(left, right), release| {
+        let sample = release_sample(release, step, pcm_hz);
+        let (l, r) = pan_gains(release.tone.pan);
+        (left + l * sample, right + r * sample)
+    })
+}
+
+/// the tones and released tones at sample `step` added into `frame`,
+/// each into the output channels its stem has in `routes`.
+pub fn routed_waves(tones: &[Tone], releases: &[Release], routes: &[Vec<usize>], step: u64, progress: u64, pcm_hz: u64,
+                    frame: &mut [f64]) {
+    let count = tones.len().max(1) as f64;
+    let sounding = tones.iter().map(|tone| (tone, tone_sample(tone, step, progress, pcm_hz) / count));
+    let released = releases.iter().map(|release| (&release.tone, release_sample(release, step, pcm_hz)));
+    for (tone, sample) in sounding.chain(released) {
+        route(sample, tone.pan, &routes[tone.stem], frame);
+    }
+}
+
+/// add `sample` into the channels of `frame` in `outputs`: panned by
+/// `pan` across a pair, or the same into each of any other number.
+pub fn route(sample: f64, pan: f64, outputs: &[usize], frame: &mut [f64]) {
+    match *outputs {
+        [left, right] => {
+            let (l, r) = pan_gains(pan);
+            frame[left] += l * sample;
+            frame[right] += r * sample;
+        }
+        _ => {
+            for &channel in outputs {
+                frame[channel] += sample;
+            }
+        }
+    }
+}
+
+/// equal-power left and right gains for a pan in [-1, 1], both 1 in the
+/// middle so a centered tone sounds as it does in mono.
+pub fn pan_gains(pan: f64) -> (f64, f64) {
+    let angle = (pan.clamp(-1_f64, 1_f64) + 1_f64) * ::std::f64::consts::FRAC_PI_4;
+    (math::cos(angle) * ::std::f64::consts::SQRT_2, math::sin(angle) * ::std::f64::consts::SQRT_2)
+}
+
+/// white noise through a bank of bandpass filters, one at each tone of
+/// the last chord and weighted by its gain, so the noise takes on the
+/// chord's spectral envelope.
+pub struct Air {
+    noise: u64,
+    /// quality factor of the filters.
+    q: f64,
+    /// biquad coefficients (b0, a1, a2) and state (x1, x2, y1, y2).
+    filters: Vec<(f64, f64, f64, f64, f64, f64, f64)>,
+    gains: Vec<f64>,
+}
+
+impl Air {
+    /// noise seeded by `seed`, filtered with quality factor `q`; every
+    /// seed gives a different stream.
+    pub fn new(seed: u64, q: f64) -> Air {
+        Air { noise: (0x9E37_79B9_7F4A_7C15 ^ seed).max(1), q, filters: vec![], gains: vec![] }
+    }
+
+    /// retune the filters to a chord. a rest keeps the last chord's.
+    pub fn tune(&mut self, tones: &[Tone], pcm_hz: u64) {
+        if tones.is_empty() {
+            return;
+        }
+
+        let nyquist = (pcm_hz as f64) / 2_f64;
+        let tuned = tones.iter().filter(|tone| tone.freq < nyquist).map(|tone| {
+            let w0 = 2_f64 * ::std::f64::consts::PI * tone.freq / (pcm_hz as f64);
+            let alpha = math::sin(w0) / (2_f64 * self.q);
+            let a0 = 1_f64 + alpha;
+            (alpha / a0, -2_f64 * math::cos(w0) / a0, (1_f64 - alpha) / a0, tone.gain)
+        });
+        let (filters, gains) = tuned.map(|(b0, a1, a2, gain)| ((b0, a1, a2, 0_f64, 0_f64, 0_f64, 0_f64), gain)).unzip();
+        self.filters = filters;
+        self.gains = gains;
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 7;
+        self.noise ^= self.noise << 17;
+        let white = (self.noise >> 11) as f64 / ((1_u64 << 52) as f64) - 1_f64;
+
+        let mut sum = 0_f64;
+        for (filter, &gain) in self.filters.iter_mut().zip(&self.gains) {
+            let (b0, a1, a2, x1, x2, y1, y2) = *filter;
+            let y = b0 * white - b0 * x2 - a1 * y1 - a2 * y2;
+            *filter = (b0, a1, a2, white, x1, y, y1);
+            sum += gain * y;
+        }
+
+        sum / (self.filters.len().max(1) as f64)
+    }
+}
+
+impl Default for Air {
+    fn default() -> Air {
+        Air::new(0, Config::default().air_q)
+    }
+}
+
+/// three band eq built from two one-pole lowpasses: the low band is
+/// below the low shelf, the high band above the high shelf, and the mid
+/// band what lies between.
+pub struct Equalizer {
+    eq: EqBands,
+    low_coefficient: f64,
+    high_coefficient: f64,
+    low: f64,
+    high: f64,
+}
+
+impl Equalizer {
+    pub fn new(eq: &EqBands, pcm_hz: u64) -> Equalizer {
+        let coefficient = |hz: f64| 1_f64 - math::exp(-2_f64 * ::std::f64::consts::PI * hz / (pcm_hz as f64));
+        Equalizer {
+            eq: eq.clone(),
+            low_coefficient: coefficient(eq.low_shelf_hz),
+            high_coefficient: coefficient(eq.high_shelf_hz),
+            low: 0_f64,
+            high: 0_f64,
+        }
+    }
+
+    pub fn process(&mut self, sample: f64) -> f64 {
+        let eq = &self.eq;
+        // non-finite samples would stick in the filter state for good
+        if !sample.is_finite() || (eq.low_gain_db == 0_f64 && eq.mid_gain_db == 0_f64 && eq.high_gain_db == 0_f64) {
+            return sample;
+        }
+
+        let gain = |db: f64| math::pow(10_f64, db / 20_f64);
+        self.low += self.low_coefficient * (sample - self.low);
+        self.high += self.high_coefficient * (sample - self.high);
+
+        gain(eq.low_gain_db) * self.low
+            + gain(eq.mid_gain_db) * (self.high - self.low)
+            + gain(eq.high_gain_db) * (sample - self.high)
+    }
+}
+
+/// soft limiter: linear up to HEADROOM, then eases toward full scale so
+/// accents and loud steps never hard clip.
+pub fn limit(sample: f64) -> f64 {
+    let magnitude = sample.abs();
+    if magnitude <= HEADROOM {
+        sample
+    } else {
+        let excess = (magnitude - HEADROOM) / (1_f64 - HEADROOM);
+        sample.signum() * (HEADROOM + (1_f64 - HEADROOM) * math::tanh(excess))
+    }
+}
+
+/// gain ramp from full to silence over the last FADE_OUT_SECS of output,
+/// `left` samples before the end.
+pub fn fade_out(sample: f64, left: u64, pcm_hz: u64) -> f64 {
+    let fade_samples = FADE_OUT_SECS * (pcm_hz as f64);
+    if (left as f64) < fade_samples {
+        sample * (left as f64) / fade_samples
+    } else {
+        sample
+    }
+}
+
+/// gain ramp from silence to full over the first FADE_IN_SECS of output.
+pub fn fade_in(sample: f64, progress: u64, pcm_hz: u64) -> f64 {
+    let fade_samples = FADE_IN_SECS * (pcm_hz as f64);
+    if (progress as f64) < fade_samples {
+        sample * (progress as f64) / fade_samples
+    } else {
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(hits: u64, slots: u64, rotation: u64) -> String {
+        euclidean(hits, slots, rotation).into_iter().map(|hit| if hit { 'x' } else { '.' }).collect()
+    }
+
+    #[test]
+    fn euclidean_spreads_hits_evenly() {
+        assert_eq!(pattern(3, 8, 0), "x..x..x.");
+        assert_eq!(pattern(2, 5, 0), "x..x.");
+        assert_eq!(pattern(4, 12, 0), "x..x..x..x..");
+    }
+
+    #[test]
+    fn euclidean_rotates_right() {
+        assert_eq!(pattern(3, 8, 1), ".x..x..x");
+        assert_eq!(pattern(3, 8, 8), pattern(3, 8, 0));
+    }
+
+    #[test]
+    fn euclidean_edges() {
+        assert_eq!(pattern(0, 4, 0), "....");
+        assert_eq!(pattern(4, 4, 0), "xxxx");
+        assert_eq!(pattern(4, 4, 3), "xxxx");
+        assert!(euclidean(3, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn chosen_shapes_sum_every_partial_below_nyquist() {
+        let (freq, pcm_hz) = (110_f64, 44100);
+        for step in &[1, 97, 12345] {
+            let (mut sum, mut norm) = (0_f64, 0_f64);
+            for k in 1..201 {
+                let sign = if k % 2 == 1 { 1_f64 } else { -1_f64 };
+                sum += sign * sine_wave(freq * k as f64, *step, pcm_hz) / k as f64;
+                norm += 1_f64 / k as f64;
+            }
+            let wave = harmonic_wave(&Oscillator::Saw, freq, u64::MAX, *step, pcm_hz);
+            assert!((wave - sum / norm).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! familiarity of moves from one noteset to the next, so characteristic
+//! progressions can be learned and not just single notes.
+
+use std::collections::BTreeMap;
+
+use {math, Frac};
+
+/// familiarity below which a move is dropped even with no floor, so
+/// moves played once don't stay in memory forever.
+static FORGOTTEN: f64 = 1e-9_f64;
+
+/// judge a move between notesets by how familiar it is, as a share of
+/// `ceiling`: 1 for a new one, falling toward 0 as it's played more.
+/// range: floats in [0, 1] and lower is better.
+pub fn judge_transition(previous: &[Frac], next: &[Frac], transitions: &Transitions, ceiling: f64) -> f64 {
+    (1_f64 / math::exp(transitions.familiarity(previous, next) / ceiling)).clamp(0_f64, 1_f64)
+}
+
+/// how familiar each move between notesets is, keyed by both sorted so
+/// the order of the voices doesn't matter.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Transitions {
+    memory: BTreeMap<(Vec<Frac>, Vec<Frac>), f64>,
+}
+
+impl Transitions {
+    pub fn new() -> Transitions {
+        Transitions { memory: BTreeMap::new() }
+    }
+
+    /// familiarity of moving from `previous` to `next`.
+    pub fn familiarity(&self, previous: &[Frac], next: &[Frac]) -> f64 {
+        *self.memory.get(&key(previous, next)).unwrap_or(&0_f64)
+    }
+
+    /// add `increment` to the move, shrinking as it nears `max` if there
+    /// is one, like remembering a note.
+    pub fn remember(&mut self, previous: &[Frac], next: &[Frac], increment: f64, max: Option<f64>) {
+        let val = self.memory.entry(key(previous, next)).or_insert(0_f64);
+        *val += match max {
+            Some(max) => increment * (1_f64 - *val / max).max(0_f64),
+            None => increment,
+        };
+    }
+
+    /// decay every move by `decay`, dropping the ones that come within
+    /// `floor`, or FORGOTTEN, of unfamiliar.
+    pub fn forget(&mut self, decay: f64, floor: f64) {
+        for val in self.memory.values_mut() {
+            *val *= decay;
+        }
+        let floor = floor.max(FORGOTTEN);
+        self.memory.retain(|_, val| *val >= floor);
+    }
+}
+
+impl Default for Transitions {
+    fn default() -> Transitions {
+        Transitions::new()
+    }
+}
+
+fn key(previous: &[Frac], next: &[Frac]) -> (Vec<Frac>, Vec<Frac>) {
+    let (mut previous, mut next) = (previous.to_vec(), next.to_vec());
+    previous.sort();
+    next.sort();
+    (previous, next)
+}
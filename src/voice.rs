@@ -0,0 +1,169 @@
+//! extra voices composing alongside the machine's own notes, each in its
+//! own register and, unless it shares the machine's, with its own memory.
+
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+
+use synth::{tones, voicing, Doubling, Gate, Oscillator, OrnamentKind, Part, Tone};
+use config::ratio;
+use {forget, most_familiar_interval, remember, search_notes, Config, Frac, Judge, Memory};
+
+/// settings for one extra voice, given as [[voices]] in a config file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceConfig {
+    /// the voice's base note as a ratio to the machine's, e.g. 0.5 for a
+    /// bass an octave down.
+    pub register: f64,
+    /// judge against and remember into the machine's memory, so the
+    /// voice reacts to the others, instead of keeping its own.
+    pub shared_memory: bool,
+    pub gain: f64,
+    /// the voice's shape, with every partial up to nyquist; none plays
+    /// the machine's, darkening familiar notes to fewer harmonics.
+    pub oscillator: Option<Oscillator>,
+    /// chance each step that a held tone of the voice is decorated, with
+    /// what, and how, like the machine's.
+    pub ornament_chance: f64,
+    pub ornament: OrnamentKind,
+    #[serde(deserialize_with = "ratio")]
+    pub ornament_neighbor: Frac,
+    pub ornament_rate_hz: f64,
+    /// euclidean gates for the voice's notes, like the machine's.
+    pub note_gates: Vec<Gate>,
+    /// extra copies of the voice's notes, like the machine's.
+    pub doublings: Vec<Doubling>,
+    /// echo the machine's own notes this many chord changes later
+    /// instead of composing, as a canon; 0 composes.
+    pub canon_delay: u64,
+    /// transpose the echo by the most familiar interval in memory when
+    /// the canon enters.
+    pub canon_transpose: bool,
+    /// output channels the voice plays through, panned across them if
+    /// there are two; empty plays through the first two, or the only one.
+    pub outputs: Vec<usize>,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> VoiceConfig {
+        VoiceConfig {
+            register: 1_f64,
+            shared_memory: false,
+            gain: 1_f64,
+            oscillator: None,
+            ornament_chance: 0_f64,
+            ornament: OrnamentKind::Trill,
+            ornament_neighbor: Frac(9, 8),
+            ornament_rate_hz: 16_f64,
+            note_gates: vec![],
+            doublings: vec![],
+            canon_delay: 0,
+            canon_transpose: false,
+            outputs: vec![],
+        }
+    }
+}
+
+pub struct Voice {
+    settings: VoiceConfig,
+    notes: Vec<Frac>,
+    previous: Vec<Frac>,
+    memory: Memory,
+    voiced: Vec<f64>,
+    resting: bool,
+    /// the machine's notes and base note at each chord change, none for
+    /// a rest, waiting to be echoed.
+    echoes: VecDeque<Option<(Vec<Frac>, f64)>>,
+    transposition: Option<f64>,
+}
+
+impl Voice {
+    pub fn new(settings: VoiceConfig, notes: Vec<Frac>, base_note: f64, config: &Config) -> Voice {
+        let voiced = voicing(base_note * settings.register, &notes, &[], config.register_octaves);
+        Voice {
+            settings,
+            previous: notes.clone(),
+            notes,
+            memory: Memory::new(),
+            voiced,
+            resting: false,
+            echoes: VecDeque::new(),
+            transposition: None,
+        }
+    }
+
+    pub fn settings(&self) -> &VoiceConfig {
+        &self.settings
+    }
+
+    pub fn notes(&self) -> &[Frac] {
+        &self.notes
+    }
+
+    /// the frequencies the voice is sounding, none while it rests.
+    pub fn voiced(&self) -> &[f64] {
+        if self.resting { &[] } else { &self.voiced }
+    }
+
+    /// pick the voice's next notes the way the machine picks its own, or
+    /// with canon_delay echo `leader`, the machine's notes unless it
+    /// rests. `shared` is the machine's memory, used with shared_memory.
+    pub fn step(&mut self, leader: Option<&[Frac]>, shared: &mut Memory, judge: &dyn Judge, rng: &mut StdRng,
+                config: &Config, base_note: f64) {
+        let memory = if self.settings.shared_memory {
+            shared
+        } else {
+            forget(&mut self.memory, config);
+            &mut self.memory
+        };
+
+        self.previous = self.notes.clone();
+        let base_note = if self.settings.canon_delay > 0 {
+            self.echoes.push_back(leader.map(|notes| (notes.to_vec(), base_note)));
+            let echo = if self.echoes.len() as u64 > self.settings.canon_delay { self.echoes.pop_front().unwrap() } else { None };
+            let (notes, base_note) = match echo {
+                Some(echo) => echo,
+                None => {
+                    self.resting = true;
+                    return;
+                }
+            };
+            let canon_transpose = self.settings.canon_transpose;
+            let transposition = *self.transposition.get_or_insert_with(|| {
+                match most_familiar_interval(memory) {
+                    Some(Frac(a, b)) if canon_transpose => a as f64 / b as f64,
+                    _ => 1_f64,
+                }
+            });
+            self.notes = notes;
+            self.resting = false;
+            base_note * transposition
+        } else {
+            self.notes = search_notes(&self.previous, memory, judge, rng, config);
+            self.resting = judge.score(&self.notes, memory) > config.tacet_score;
+            if self.resting {
+                return;
+            }
+            base_note
+        };
+
+        remember(&self.notes, memory, config);
+        self.voiced = voicing(base_note * self.settings.register, &self.notes, &self.voiced, config.register_octaves);
+    }
+
+    pub fn tones(&self, shared: &Memory, config: &Config, level: f64, step: u64) -> Vec<Tone> {
+        if self.resting {
+            return vec![];
+        }
+
+        let memory = if self.settings.shared_memory { shared } else { &self.memory };
+        let part = Part {
+            oscillator: self.settings.oscillator,
+            note_gates: &self.settings.note_gates,
+            doublings: &self.settings.doublings,
+            config,
+        };
+        tones(&self.notes, &self.voiced, &self.previous, memory, level * self.settings.gain, step, &part)
+    }
+}
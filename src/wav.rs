@@ -0,0 +1,115 @@
+//! riff/wave container for 16 bit pcm.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// byte count written into the header until `finish` knows the real
+/// one, the usual stand-in for a stream of unknown length.
+static UNKNOWN_LENGTH: u32 = 0xFFFF_FFFF;
+
+/// writes a wave header, passes the sample data through, and fills in
+/// the chunk sizes on `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    inner: W,
+    data_bytes: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut inner: W, sample_rate: u32, channels: u16) -> io::Result<WavWriter<W>> {
+        let bits = 16_u16;
+        let block_align = channels * bits / 8;
+
+        inner.write_all(b"RIFF")?;
+        inner.write_u32::<LittleEndian>(UNKNOWN_LENGTH)?;
+        inner.write_all(b"WAVE")?;
+        inner.write_all(b"fmt ")?;
+        inner.write_u32::<LittleEndian>(16)?;
+        inner.write_u16::<LittleEndian>(1)?;
+        inner.write_u16::<LittleEndian>(channels)?;
+        inner.write_u32::<LittleEndian>(sample_rate)?;
+        inner.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;
+        inner.write_u16::<LittleEndian>(block_align)?;
+        inner.write_u16::<LittleEndian>(bits)?;
+        inner.write_all(b"data")?;
+        inner.write_u32::<LittleEndian>(UNKNOWN_LENGTH)?;
+
+        Ok(WavWriter { inner, data_bytes: 0 })
+    }
+
+    /// patch the riff and data sizes and flush. streams past 4 GiB keep
+    /// the unknown-length sizes.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.data_bytes + 36 <= UNKNOWN_LENGTH as u64 {
+            self.inner.seek(SeekFrom::Start(4))?;
+            self.inner.write_u32::<LittleEndian>(self.data_bytes as u32 + 36)?;
+            self.inner.seek(SeekFrom::Start(40))?;
+            self.inner.write_u32::<LittleEndian>(self.data_bytes as u32)?;
+            self.inner.seek(SeekFrom::End(0))?;
+        }
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write + Seek> Write for WavWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.data_bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    #[test]
+    fn header_fields() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 44100, 2).unwrap();
+        wav.write_all(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 36 + 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes, 16), 16);
+        assert_eq!(u16_at(&bytes, 20), 1);
+        assert_eq!(u16_at(&bytes, 22), 2);
+        assert_eq!(u32_at(&bytes, 24), 44100);
+        assert_eq!(u32_at(&bytes, 28), 44100 * 4);
+        assert_eq!(u16_at(&bytes, 32), 4);
+        assert_eq!(u16_at(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), 8);
+        assert_eq!(&bytes[44..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn unknown_length_until_finished() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 8000, 1).unwrap();
+        wav.write_all(&[0, 0]).unwrap();
+        let bytes = wav.inner.get_ref();
+
+        assert_eq!(u32_at(bytes, 4), UNKNOWN_LENGTH);
+        assert_eq!(u32_at(bytes, 40), UNKNOWN_LENGTH);
+        assert_eq!(u16_at(bytes, 22), 1);
+        assert_eq!(u32_at(bytes, 28), 8000 * 2);
+    }
+}